@@ -1,17 +1,22 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::ReadDir;
-use std::io::prelude::*;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
 use regex::Regex;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use rand::Rng;
+use tauri::State;
 
-use crate::{deck, review, note};
+use crate::config::{self, LeechAction, NewOrder, QueueMix, ReviewOrder};
+use crate::context::Context;
+use crate::history::{self, EntryKind, ReviewLogEntry};
+use crate::{deck, limits, note, review};
 use crate::note::Note;
-use crate::review::{Review, ReviewScore, ReviewState};
+use crate::review::{DayBoundary, DueCounts, Review, ReviewScore, ReviewState};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Card {
@@ -25,12 +30,20 @@ pub struct Card {
     steps: u32,
     template: String,
     score: ReviewScore,
+    stability: Option<f64>,
+    difficulty: Option<f64>,
+    #[serde(default)]
+    lapses: u32,
+    #[serde(default)]
+    suspended: bool,
+    #[serde(default)]
+    buried_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    position: Option<u32>,
 }
 
-pub fn get_review_path(card: Card) -> PathBuf {
-    deck::get_deck_path(&card.deck_id)
-        .join("reviews")
-        .join(format!("{}.jsonl", &card.note_id))
+pub fn get_review_path(collection: &str, deck: &str, note_id: &str) -> PathBuf {
+    history::get_reviews_path(collection, deck).join(format!("{}.jsonl", note_id))
 }
 
 impl From<Card> for Note {
@@ -41,19 +54,62 @@ impl From<Card> for Note {
 
 impl From<Card> for Review {
     fn from(card: Card) -> Self {
-        Review::new(card.due, card.interval, card.ease, card.state, card.steps)
+        Review {
+            stability: card.stability,
+            difficulty: card.difficulty,
+            lapses: card.lapses,
+            suspended: card.suspended,
+            buried_until: card.buried_until,
+            position: card.position,
+            ..Review::new(card.due, card.interval, card.ease, card.state, card.steps)
+        }
     }
 }
 
+// Which part of the review queue a card belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueueKind {
+    New,
+    Learning,
+    Review,
+}
+
 impl Card {
-    fn update_from_review(self, review: Review, score: ReviewScore) -> Card {
+    pub fn queue_kind(&self) -> QueueKind {
+        match (&self.state, self.due) {
+            (ReviewState::New, None) => QueueKind::New,
+            (ReviewState::Learned, _) => QueueKind::Review,
+            _ => QueueKind::Learning,
+        }
+    }
+
+    pub fn due(&self) -> Option<DateTime<Utc>> {
+        self.due
+    }
+
+    pub fn deck_id(&self) -> &str {
+        &self.deck_id
+    }
+
+    // Whether both are the same card, whatever state each is in
+    pub fn is_same(&self, other: &Card) -> bool {
+        self.deck_id == other.deck_id && self.note_id == other.note_id && self.card_num == other.card_num
+    }
+
+    fn update_from_review(self, review: Review, score: Option<ReviewScore>) -> Card {
         Card {
             due: review.due,
             interval: review.interval,
             ease: review.ease,
             state: review.state,
             steps: review.steps,
-            score,
+            stability: review.stability,
+            difficulty: review.difficulty,
+            lapses: review.lapses,
+            suspended: review.suspended,
+            buried_until: review.buried_until,
+            position: review.position,
+            score: score.unwrap_or_else(|| self.score.clone()),
             ..self
         }
     }
@@ -72,16 +128,49 @@ impl Default for Card {
             deck_id: String::from("test"),
             state: ReviewState::New,
             score: ReviewScore::Good,
+            stability: None,
+            difficulty: None,
+            lapses: 0,
+            suspended: false,
+            buried_until: None,
+            position: None,
         }
     }
 }
 
 #[tauri::command]
-pub fn render_card(card: Card, back: bool) -> Result<String, String> {
-    note::render_note_card(card.clone().into(), card.card_num, back)
+pub fn render_card(state: State<'_, Context>, card: Card, back: bool) -> Result<String, String> {
+    note::render_note_card(state.inner(), card.clone().into(), card.card_num, back)
+}
+
+fn get_cards_for_note(collection: &str, deck: &str, note_id: &str, template: &str) -> Vec<Card> {
+    let mut cards = BTreeMap::new();
+    cards.insert(
+        1,
+        Card {
+            deck_id: deck.to_string(),
+            note_id: note_id.to_string(),
+            template: template.to_string(),
+            ..Card::default()
+        },
+    );
+
+    let entries = history::read_log(&get_review_path(collection, deck, note_id));
+    for (card_num, entry) in history::latest_entries(entries) {
+        let card = Card {
+            deck_id: deck.to_string(),
+            note_id: note_id.to_string(),
+            template: template.to_string(),
+            card_num,
+            ..Card::default()
+        };
+        cards.insert(card_num, card.update_from_review(entry.after, entry.score));
+    }
+
+    cards.into_values().collect()
 }
 
-fn get_due_cards_from_paths(deck: &str, paths: ReadDir) -> Vec<Card> {
+fn get_cards_from_paths(collection: &str, deck: &str, paths: ReadDir) -> Vec<Card> {
     let note_filename_regex = Regex::new("([^_]*)?_?(.*).md").unwrap();
     paths
         .filter_map(|path| match path {
@@ -98,49 +187,963 @@ fn get_due_cards_from_paths(deck: &str, paths: ReadDir) -> Vec<Card> {
                 None => None,
                 Some(captures) => {
                     let note_id = captures.get(1).map_or("basic", |x| x.as_str());
+                    let template = captures.get(2).map_or("basic", |x| x.as_str());
 
-                    Some(Card {
-                        deck_id: deck.to_string(),
-                        note_id: note_id.to_string(),
-                        ..Card::default()
-                    })
+                    Some(get_cards_for_note(collection, deck, note_id, template))
                 }
             },
         )
-        .filter(|x| match x.due {
-            None => true,
-            Some(due) => due < Utc::now(),
-        })
+        .flatten()
         // This is where in the future we'll want to derive other cards based on
         // their templates / cloze deletions
-        // we'll also need to parse the filename to get the note id + the template
         .collect()
 }
 
-#[tauri::command]
-pub fn list_cards_to_review(deck: &str) -> Result<Vec<Card>, String> {
-    match fs::read_dir(deck::get_deck_path(deck)) {
-        Ok(paths) => Ok(get_due_cards_from_paths(deck, paths)),
+// Replays every log in the deck to count the cards due on each day. This reads
+// the whole deck, so a review session does it once and keeps the counts.
+pub fn get_due_counts(collection: &str, deck: &str, days: DayBoundary) -> DueCounts {
+    let mut due_counts = DueCounts::new();
+    for entries in history::read_deck_logs(collection, deck).into_values() {
+        for entry in history::latest_entries(history::effective_entries(entries)).into_values() {
+            if let Some(due) = entry.after.due {
+                *due_counts.entry(days.day(due)).or_insert(0) += 1;
+            }
+        }
+    }
+    due_counts
+}
+
+pub fn get_deck_cards(context: &Context, deck: &str) -> Result<Vec<Card>, String> {
+    let collection = context.get_collection_path();
+    match fs::read_dir(deck::get_deck_path(&collection, deck)) {
+        Ok(paths) => Ok(get_cards_from_paths(&collection, deck, paths)),
         Err(err) => Err(err.to_string()),
     }
 }
 
+fn new_order_key(card: &Card, order: &NewOrder, day: usize) -> (u32, u64, String, u32) {
+    // Repositioned cards come first, in the order they were given
+    let position = card.position.unwrap_or(u32::MAX);
+    match order {
+        NewOrder::Created => (
+            position,
+            card.note_id.parse().unwrap_or(u64::MAX),
+            card.note_id.clone(),
+            card.card_num,
+        ),
+        NewOrder::Random => (position, review::fuzz_seed(&card.note_id, card.card_num, day), String::new(), 0),
+        NewOrder::Filename => (
+            position,
+            0,
+            format!("{}_{}", card.note_id, card.template),
+            card.card_num,
+        ),
+    }
+}
+
+fn sort_reviews(cards: &mut [Card], order: &ReviewOrder, time: DateTime<Utc>, day: usize) {
+    let key = |card: &Card| -> f64 {
+        let review = Review::from(card.clone());
+        match order {
+            ReviewOrder::Due => review.due.map_or(0.0, |due| due.timestamp() as f64),
+            ReviewOrder::Random => review::fuzz_seed(&card.note_id, card.card_num, day) as f64,
+            ReviewOrder::Retrievability => review.retrievability(time),
+            ReviewOrder::Overdueness => -review.overdueness(time),
+        }
+    };
+    cards.sort_by(|a, b| {
+        key(a)
+            .partial_cmp(&key(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| (&a.note_id, a.card_num).cmp(&(&b.note_id, b.card_num)))
+    });
+}
+
+// Puts `cards` before, after or spread evenly through `reviews`
+fn mix(reviews: Vec<Card>, cards: Vec<Card>, mix: &QueueMix) -> Vec<Card> {
+    match mix {
+        QueueMix::Before => cards.into_iter().chain(reviews).collect(),
+        QueueMix::After => reviews.into_iter().chain(cards).collect(),
+        QueueMix::Mixed => {
+            let spread = |cards: Vec<Card>| {
+                let count = cards.len() as f64 + 1.0;
+                cards
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(index, card)| ((index as f64 + 1.0) / count, card))
+            };
+            let mut queue: Vec<(f64, Card)> = spread(reviews).chain(spread(cards)).collect();
+            queue.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            queue.into_iter().map(|(_, card)| card).collect()
+        }
+    }
+}
+
+// Due cards from a deck and the decks nested in it, in the order set in the
+// deck's config. New cards and reviews from each nested deck are held to what
+// is left of its own limits, and all of them together to the deck's. Cards in
+// learning aren't limited.
+pub fn get_cards_to_review(context: &Context, deck: &str) -> Result<Vec<Card>, String> {
+    let collection = context.get_collection_path();
+    let config = config::load_deck_config(&collection, deck)?;
+    let now = Utc::now();
+    let days = config::load_day_boundary(&collection, now)?;
+    // Random orders stay the same for the whole day
+    let day = days.day(now).num_days_from_ce() as usize;
+    let studied = limits::get_studied_by_deck(&collection, &limits::get_limiting_tree(deck), days, now);
+
+    let (mut new, mut learning, mut reviews) = (vec![], vec![], vec![]);
+    for deck in deck::get_deck_tree(&collection, deck) {
+        let remaining = limits::get_remaining(&collection, &deck, &studied)?;
+        let (mut deck_new, mut deck_reviews) = (vec![], vec![]);
+        for card in get_deck_cards(context, &deck)? {
+            let review = Review::from(card.clone());
+            if review.is_hidden(now) || !days.is_due(&review, now) {
+                continue;
+            }
+            match card.queue_kind() {
+                QueueKind::New => deck_new.push(card),
+                QueueKind::Review => deck_reviews.push(card),
+                QueueKind::Learning => learning.push(card),
+            }
+        }
+        deck_new.sort_by_key(|card| new_order_key(card, &config.new_order, day));
+        new.extend(deck_new.into_iter().take(remaining.new as usize));
+        sort_reviews(&mut deck_reviews, &config.review_order, now, day);
+        reviews.extend(deck_reviews.into_iter().take(remaining.reviews as usize));
+    }
+
+    let remaining = limits::get_remaining(&collection, deck, &studied)?;
+    new.sort_by_key(|card| new_order_key(card, &config.new_order, day));
+    new.truncate(remaining.new as usize);
+    sort_reviews(&mut reviews, &config.review_order, now, day);
+    reviews.truncate(remaining.reviews as usize);
+    learning.sort_by_key(|card| card.due);
+
+    let queue = mix(reviews, new, &config.new_mix);
+    Ok(mix(queue, learning, &config.learning_mix))
+}
+
+pub fn get_leeches(context: &Context, deck: &str) -> Result<Vec<Card>, String> {
+    let threshold = config::load_deck_config(&context.get_collection_path(), deck)?.leech_threshold;
+    Ok(get_deck_cards(context, deck)?
+        .into_iter()
+        .filter(|x| x.lapses >= threshold)
+        .collect())
+}
+
 #[tauri::command]
-pub fn review_card(card: Card, score: ReviewScore) -> Result<String, String> {
-    let new_review = review::score_card(card.clone().into(), Utc::now(), score.clone());
+pub fn list_leeches(state: State<'_, Context>, deck: &str) -> Result<Vec<Card>, String> {
+    get_leeches(state.inner(), deck)
+}
+
+#[tauri::command]
+pub fn list_cards_to_review(state: State<'_, Context>, deck: &str) -> Result<Vec<Card>, String> {
+    get_cards_to_review(state.inner(), deck)
+}
+
+// Schedules the card and logs the review. The answer time is how long the
+// card was shown before it was scored, if known. Load balancing goes by the
+// due counts of the card's deck.
+pub fn answer_card(
+    context: &Context,
+    card: Card,
+    score: ReviewScore,
+    answer_time: Option<Duration>,
+    due_counts: &DueCounts,
+) -> Result<Card, String> {
+    let collection = context.get_collection_path();
+    let config = config::load_deck_config(&collection, &card.deck_id)?;
+    let path = get_review_path(&collection, &card.deck_id, &card.note_id);
+
+    let entries = history::effective_entries(history::read_log(&path));
+    let reviews = entries.iter().filter(|entry| entry.card_num == card.card_num).count();
+    let seed = review::fuzz_seed(&card.note_id, card.card_num, reviews);
+
+    // Leech handling goes by the lapses and suspension in the log, not
+    // whatever the caller sent
+    let logged = history::latest_entries(entries)
+        .remove(&card.card_num)
+        .map(|entry| entry.after)
+        .unwrap_or_default();
+    let card = Card {
+        lapses: logged.lapses,
+        suspended: logged.suspended,
+        ..card
+    };
+
+    let time = Utc::now();
+    let days = config::load_day_boundary(&collection, time)?;
 
-    let new_card = card.clone().update_from_review(new_review, score.clone());
+    let mut new_review = config
+        .scheduler
+        .schedule(card.clone().into(), time, score.clone(), seed, due_counts, days);
 
-    match fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(get_review_path(card.clone().into()))
+    let leech = new_review.lapses > card.lapses && new_review.lapses >= config.leech_threshold;
+    if leech && config.leech_action == LeechAction::Suspend {
+        new_review.suspended = true;
+    }
+
+    let maximum_millis = u64::from(config.maximum_answer_seconds) * 1000;
+    let entry = ReviewLogEntry {
+        answer_millis: answer_time.map(|answer_time| (answer_time.num_milliseconds().max(0) as u64).min(maximum_millis)),
+        ..ReviewLogEntry::new(card.card_num, time, score.clone(), card.clone().into(), new_review.clone())
+    };
+    history::append_entry(&path, &entry)?;
+    // Tagged only once the answer is logged, so a failed append leaves no tag behind
+    if leech {
+        note::add_tag(context, &card.clone().into(), "leech")?;
+    }
+
+    let siblings = get_cards_for_note(&collection, &card.deck_id, &card.note_id, &card.template)
+        .into_iter()
+        .filter(|sibling| sibling.card_num != card.card_num && !Review::from(sibling.clone()).is_hidden(time))
+        .filter(|sibling| match (&sibling.state, sibling.due) {
+            (ReviewState::New, None) => config.bury_new_siblings,
+            (ReviewState::Learned, _) => config.bury_review_siblings,
+            _ => false,
+        })
+        .collect();
+    bury_at(context, siblings, time)?;
+
+    Ok(card.update_from_review(new_review, Some(score)))
+}
+
+// What each answer would schedule, from Again to Easy, without fuzz. Nothing
+// is written to the review log.
+pub fn preview(context: &Context, card: &Card, time: DateTime<Utc>) -> Result<Vec<String>, String> {
+    let config = config::load_deck_config(&context.get_collection_path(), &card.deck_id)?.scheduler;
+    Ok([ReviewScore::Again, ReviewScore::Hard, ReviewScore::Good, ReviewScore::Easy]
+        .into_iter()
+        .map(|score| {
+            let review = config.scheduler().schedule(card.clone().into(), time, score);
+            review::format_interval(&review::limit_interval(review, config.sm2.maximum_interval), time)
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub fn preview_intervals(state: State<'_, Context>, card: Card) -> Result<Vec<String>, String> {
+    preview(state.inner(), &card, Utc::now())
+}
+
+// Takes back the card's latest review or manual change, restoring the state
+// before it. Siblings buried by that review are unburied too, and the leech
+// tag it added is removed once no card of the note is a leech. Calling it
+// again undoes the change before that.
+pub fn undo(context: &Context, card: Card) -> Result<Card, String> {
+    let collection = context.get_collection_path();
+    let path = get_review_path(&collection, &card.deck_id, &card.note_id);
+    let entries = history::effective_entries(history::read_log(&path));
+
+    let undone = match entries.iter().rev().find(|entry| entry.card_num == card.card_num) {
+        Some(entry) => entry,
+        None => return Err("Nothing to undo".to_string()),
+    };
+
+    let time = Utc::now();
+    history::append_entry(&path, &ReviewLogEntry::undo(undone, time))?;
+    if undone.kind == EntryKind::Review {
+        for (card_num, entry) in history::latest_entries(entries.clone()) {
+            if card_num != card.card_num
+                && entry.kind == EntryKind::Manual
+                && entry.reviewed_at == undone.reviewed_at
+            {
+                history::append_entry(&path, &ReviewLogEntry::undo(&entry, time))?;
+            }
+        }
+    }
+
+    let restored = reload(context, &card)?;
+    let threshold = config::load_deck_config(&collection, &card.deck_id)?.leech_threshold;
+    if undone.after.lapses >= threshold
+        && restored.lapses < threshold
+        && get_siblings(context, &restored).iter().all(|sibling| sibling.lapses < threshold)
     {
-        Ok(mut file) => match file.write_all(&serde_json::to_vec(&new_card).unwrap()) {
-            Ok(..) => Ok("".to_string()),
-            Err(..) => Err("".to_string()),
-        },
-        Err(..) => Err("".to_string()),
+        note::remove_tag(context, &restored.clone().into(), "leech")?;
     }
+    Ok(restored)
+}
+
+// The card's current state according to its review log
+pub fn reload(context: &Context, card: &Card) -> Result<Card, String> {
+    get_cards_for_note(&context.get_collection_path(), &card.deck_id, &card.note_id, &card.template)
+        .into_iter()
+        .find(|x| x.card_num == card.card_num)
+        .ok_or_else(|| "Card not found".to_string())
+}
+
+// The other cards of the card's note
+pub fn get_siblings(context: &Context, card: &Card) -> Vec<Card> {
+    get_cards_for_note(&context.get_collection_path(), &card.deck_id, &card.note_id, &card.template)
+        .into_iter()
+        .filter(|x| x.card_num != card.card_num)
+        .collect()
+}
+
+// Records a manual change to each card in the review log, so replaying the
+// log keeps the change
+fn change_cards(
+    context: &Context,
+    cards: Vec<Card>,
+    time: DateTime<Utc>,
+    kind: EntryKind,
+    mut change: impl FnMut(Review) -> Review,
+) -> Result<Vec<Card>, String> {
+    let collection = context.get_collection_path();
+    cards
+        .into_iter()
+        .map(|card| {
+            let before = Review::from(card.clone());
+            let after = change(before.clone());
+            let entry = ReviewLogEntry {
+                kind: kind.clone(),
+                ..ReviewLogEntry::manual(card.card_num, time, before, after.clone())
+            };
+            history::append_entry(&get_review_path(&collection, &card.deck_id, &card.note_id), &entry)?;
+            Ok(card.update_from_review(after, None))
+        })
+        .collect()
+}
+
+pub fn suspend(context: &Context, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    change_cards(context, cards, Utc::now(), EntryKind::Manual, |review| Review {
+        suspended: true,
+        ..review
+    })
+}
+
+pub fn unsuspend(context: &Context, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    change_cards(context, cards, Utc::now(), EntryKind::Manual, |review| Review {
+        suspended: false,
+        ..review
+    })
+}
+
+// Buried cards come back at the start of the next day
+fn bury_at(context: &Context, cards: Vec<Card>, time: DateTime<Utc>) -> Result<Vec<Card>, String> {
+    let until = config::load_day_boundary(&context.get_collection_path(), time)?.next_day(time);
+    change_cards(context, cards, time, EntryKind::Manual, |review| Review {
+        buried_until: Some(until),
+        ..review
+    })
+}
+
+// Puts cards back to New, keeping their place in the new card queue and any
+// suspension or burial. Without history, the earlier reviews no longer count
+// towards lapses, the review count or optimization.
+pub fn forget(context: &Context, cards: Vec<Card>, keep_history: bool) -> Result<Vec<Card>, String> {
+    let kind = if keep_history { EntryKind::Manual } else { EntryKind::Forget };
+    change_cards(context, cards, Utc::now(), kind, |review| Review {
+        lapses: if keep_history { review.lapses } else { 0 },
+        position: review.position,
+        suspended: review.suspended,
+        buried_until: review.buried_until,
+        ..Review::default()
+    })
+}
+
+// Makes cards due the given number of days from today, or a random number of
+// days between `days` and `until_days`. Cards that aren't in review yet
+// graduate with that many days as their interval.
+pub fn set_due(
+    context: &Context,
+    cards: Vec<Card>,
+    days: u32,
+    until_days: Option<u32>,
+    rng: &mut impl Rng,
+) -> Result<Vec<Card>, String> {
+    let time = Utc::now();
+    let boundary = config::load_day_boundary(&context.get_collection_path(), time)?;
+    let today = boundary.day(time);
+    let latest = until_days.unwrap_or(days).max(days);
+
+    change_cards(context, cards, time, EntryKind::Manual, |review| {
+        let days = rng.gen_range(days..=latest);
+        let interval = match review.state {
+            ReviewState::Learned => review.interval,
+            _ => f64::from(days.max(1)),
+        };
+        Review {
+            due: Some(boundary.start(today + Duration::days(days.into()))),
+            interval,
+            state: ReviewState::Learned,
+            steps: 0,
+            buried_until: None,
+            ..review
+        }
+    })
+}
+
+// Gives new cards queue positions in the order they're listed, from `start`
+// in increments of `step`. Cards that have been studied are left alone.
+pub fn reposition(context: &Context, cards: Vec<Card>, start: u32, step: u32) -> Result<Vec<Card>, String> {
+    let new_cards = cards
+        .into_iter()
+        .filter(|card| card.state == ReviewState::New && card.due.is_none())
+        .collect();
+    let mut position = start;
+    change_cards(context, new_cards, Utc::now(), EntryKind::Manual, |review| {
+        let review = Review {
+            position: Some(position),
+            ..review
+        };
+        position += step;
+        review
+    })
+}
+
+// These take a list so a single card or a whole set of search results can be
+// changed at once
+pub fn bury(context: &Context, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    bury_at(context, cards, Utc::now())
+}
+
+#[tauri::command]
+pub fn suspend_cards(state: State<'_, Context>, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    suspend(state.inner(), cards)
+}
+
+#[tauri::command]
+pub fn unsuspend_cards(state: State<'_, Context>, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    unsuspend(state.inner(), cards)
+}
+
+#[tauri::command]
+pub fn bury_cards(state: State<'_, Context>, cards: Vec<Card>) -> Result<Vec<Card>, String> {
+    bury(state.inner(), cards)
+}
+
+#[tauri::command]
+pub fn forget_cards(state: State<'_, Context>, cards: Vec<Card>, keep_history: bool) -> Result<Vec<Card>, String> {
+    forget(state.inner(), cards, keep_history)
 }
 
+#[tauri::command]
+pub fn set_due_date(
+    state: State<'_, Context>,
+    cards: Vec<Card>,
+    days: u32,
+    until_days: Option<u32>,
+) -> Result<Vec<Card>, String> {
+    set_due(state.inner(), cards, days, until_days, &mut rand::thread_rng())
+}
+
+#[tauri::command]
+pub fn reposition_cards(
+    state: State<'_, Context>,
+    cards: Vec<Card>,
+    start: u32,
+    step: u32,
+) -> Result<Vec<Card>, String> {
+    reposition(state.inner(), cards, start, step)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::card::{
+        answer_card, bury, forget, get_cards_to_review, get_deck_cards, get_leeches, get_review_path, preview,
+        reposition, set_due, suspend, undo, unsuspend, Card,
+    };
+    use crate::config::{self, DECK_CONFIG_FILE};
+    use crate::context::Context;
+    use crate::history::{append_entry, ReviewLogEntry};
+    use crate::note::{get_note_fields, get_tags};
+    use crate::review::{DueCounts, Review, ReviewScore, ReviewState};
+    use crate::test_helpers::scaffold_deck;
+    use chrono::{Duration, Utc};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::fs;
+    use std::path::Path;
+
+    fn snapshot(note_id: &str, state: ReviewState, due_in: Duration) -> Vec<u8> {
+        serde_json::to_vec(&Card {
+            note_id: note_id.to_string(),
+            deck_id: "testdeck".to_string(),
+            state,
+            due: Some(Utc::now() + due_in),
+            ..Card::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn cards_without_reviews_are_new() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+
+        let cards = get_cards_to_review(&Context::from(collection), "testdeck").unwrap();
+
+        assert_eq!(2, cards.len());
+        assert!(cards.iter().all(|card| card.state == ReviewState::New && card.due.is_none()));
+    }
+
+    #[test]
+    fn cards_use_latest_review_snapshot() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+
+        let mut log = snapshot("123", ReviewState::New, Duration::minutes(-5));
+        log.extend(snapshot("123", ReviewState::Learned, Duration::days(1)));
+        fs::write(get_review_path(collection, "testdeck", "123"), log).unwrap();
+
+        let mut log = snapshot("456", ReviewState::New, Duration::minutes(10));
+        log.extend(snapshot("456", ReviewState::Learned, Duration::days(-1)));
+        fs::write(get_review_path(collection, "testdeck", "456"), log).unwrap();
+
+        let cards = get_cards_to_review(&Context::from(collection), "testdeck").unwrap();
+
+        assert_eq!(1, cards.len(), "Only the overdue card should be listed");
+        assert_eq!("456", cards[0].note_id);
+        assert_eq!(ReviewState::Learned, cards[0].state);
+    }
+
+    #[test]
+    fn lapsing_past_threshold_marks_leech() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        fs::write(
+            Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE),
+            "leech_threshold: 2\nleech_action: suspend\n",
+        )
+        .unwrap();
+        let context = Context::from(collection);
+        let learned = Review {
+            state: ReviewState::Learned,
+            due: Some(Utc::now()),
+            lapses: 1,
+            ..Review::default()
+        };
+        let entry = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned);
+        append_entry(&get_review_path(collection, "testdeck", "123"), &entry).unwrap();
+
+        let card = Card {
+            note_id: "123".to_string(),
+            deck_id: "testdeck".to_string(),
+            state: ReviewState::Learned,
+            due: Some(Utc::now()),
+            ..Card::default()
+        };
+        assert!(get_leeches(&context, "testdeck").unwrap().is_empty());
+
+        // Lapses claimed by the caller don't count, only those in the log
+        let other = Card {
+            note_id: "456".to_string(),
+            lapses: 5,
+            ..card.clone()
+        };
+        let other = answer_card(&context, other, ReviewScore::Again, None, &DueCounts::new()).unwrap();
+        assert_eq!(1, other.lapses);
+        assert!(!other.suspended);
+
+        let card = answer_card(&context, card, ReviewScore::Again, None, &DueCounts::new()).unwrap();
+
+        assert_eq!(2, card.lapses);
+        assert!(card.suspended);
+        let leeches = get_leeches(&context, "testdeck").unwrap();
+        assert_eq!(1, leeches.len());
+        assert_eq!("123", leeches[0].note_id);
+        assert!(
+            get_cards_to_review(&context, "testdeck")
+                .unwrap()
+                .iter()
+                .all(|card| card.note_id != "123"),
+            "Suspended leeches should not be reviewed"
+        );
+        let fields = get_note_fields(&context, &card.clone().into()).unwrap();
+        assert_eq!(vec!["leech".to_string()], get_tags(&fields));
+
+        // Undoing the lapse takes back the tag and the suspension with it
+        let card = undo(&context, card).unwrap();
+        assert_eq!(1, card.lapses);
+        assert!(!card.suspended);
+        assert!(get_leeches(&context, "testdeck").unwrap().is_empty());
+        let fields = get_note_fields(&context, &card.into()).unwrap();
+        assert!(get_tags(&fields).is_empty());
+    }
+
+    #[test]
+    fn suspended_cards_are_not_reviewed() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+
+        let suspended = suspend(&context, cards.clone()).unwrap();
+
+        assert!(suspended.iter().all(|card| card.suspended));
+        assert!(get_cards_to_review(&context, "testdeck").unwrap().is_empty());
+        assert_eq!(2, get_deck_cards(&context, "testdeck").unwrap().len());
+
+        unsuspend(&context, vec![suspended[0].clone()]).unwrap();
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(1, cards.len());
+        assert_eq!(suspended[0].note_id, cards[0].note_id);
+        assert!(!cards[0].suspended);
+    }
+
+    #[test]
+    fn buried_cards_return_the_next_day() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+
+        let buried = bury(&context, vec![cards[0].clone()]).unwrap();
+
+        let now = Utc::now();
+        assert_eq!(Some(config::load_day_boundary(collection, now).unwrap().next_day(now)), buried[0].buried_until);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(1, cards.len());
+        assert_ne!(buried[0].note_id, cards[0].note_id);
+    }
+
+    // Gives note 123 a second card that is already in review
+    fn add_review_sibling(collection: &str) {
+        let learned = Review {
+            due: Some(Utc::now() - Duration::days(1)),
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let entry = ReviewLogEntry::new(2, Utc::now(), ReviewScore::Good, Review::default(), learned);
+        append_entry(&get_review_path(collection, "testdeck", "123"), &entry).unwrap();
+    }
+
+    #[test]
+    fn answering_a_card_buries_its_siblings() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        add_review_sibling(collection);
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(3, cards.len());
+
+        let first = cards.into_iter().find(|card| card.note_id == "123" && card.card_num == 1).unwrap();
+        answer_card(&context, first, ReviewScore::Good, None, &DueCounts::new()).unwrap();
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert!(cards.iter().all(|card| card.note_id != "123"), "{:?}", cards);
+        let sibling = get_deck_cards(&context, "testdeck")
+            .unwrap()
+            .into_iter()
+            .find(|card| card.note_id == "123" && card.card_num == 2)
+            .unwrap();
+        let now = Utc::now();
+        assert_eq!(Some(config::load_day_boundary(collection, now).unwrap().next_day(now)), sibling.buried_until);
+    }
+
+    #[test]
+    fn sibling_burying_can_be_turned_off() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        add_review_sibling(collection);
+        fs::write(
+            Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE),
+            "bury_review_siblings: false\n",
+        )
+        .unwrap();
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+
+        let first = cards.into_iter().find(|card| card.note_id == "123" && card.card_num == 1).unwrap();
+        answer_card(&context, first, ReviewScore::Good, None, &DueCounts::new()).unwrap();
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert!(cards.iter().any(|card| card.note_id == "123" && card.card_num == 2));
+    }
+
+    #[test]
+    fn undo_steps_back_through_reviews() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let new = Card {
+            note_id: "123".to_string(),
+            deck_id: "testdeck".to_string(),
+            ..Card::default()
+        };
+
+        let first = answer_card(&context, new.clone(), ReviewScore::Good, None, &DueCounts::new()).unwrap();
+        let second = answer_card(&context, first.clone(), ReviewScore::Again, None, &DueCounts::new()).unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(first, undo(&context, second.clone()).unwrap());
+        assert_eq!(new, undo(&context, second.clone()).unwrap());
+        assert!(undo(&context, second).is_err());
+        assert_eq!(2, get_cards_to_review(&context, "testdeck").unwrap().len());
+    }
+
+    #[test]
+    fn undo_unburies_siblings() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        add_review_sibling(collection);
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+
+        let first = cards.into_iter().find(|card| card.note_id == "123" && card.card_num == 1).unwrap();
+        let answered = answer_card(&context, first.clone(), ReviewScore::Good, None, &DueCounts::new()).unwrap();
+        assert_eq!(1, get_cards_to_review(&context, "testdeck").unwrap().len());
+
+        assert_eq!(first, undo(&context, answered).unwrap());
+        assert_eq!(3, get_cards_to_review(&context, "testdeck").unwrap().len());
+    }
+
+    fn lapsed_card(context: &Context) -> Card {
+        let card = Card {
+            note_id: "123".to_string(),
+            deck_id: "testdeck".to_string(),
+            state: ReviewState::Learned,
+            due: Some(Utc::now()),
+            interval: 10.0,
+            ..Card::default()
+        };
+        answer_card(context, card, ReviewScore::Again, None, &DueCounts::new()).unwrap()
+    }
+
+    #[test]
+    fn forget_resets_cards_to_new() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+
+        let kept = forget(&context, vec![lapsed_card(&context)], true).unwrap();
+        assert_eq!(ReviewState::New, kept[0].state);
+        assert_eq!(None, kept[0].due);
+        assert_eq!(1, kept[0].lapses);
+
+        let suspended = suspend(&context, vec![lapsed_card(&context)]).unwrap();
+        let forgotten = forget(&context, suspended, false).unwrap();
+        assert_eq!(0, forgotten[0].lapses);
+        assert!(forgotten[0].suspended, "Forgetting shouldn't unsuspend");
+        let path = get_review_path(collection, "testdeck", "123");
+        let effective = crate::history::effective_entries(crate::history::read_log(&path));
+        assert_eq!(1, effective.len(), "Earlier reviews should be dropped");
+
+        // Both lapses count, the second on top of the one kept in the log
+        let restored = undo(&context, forgotten[0].clone()).unwrap();
+        assert_eq!(ReviewState::Relearning, restored.state);
+        assert_eq!(2, restored.lapses);
+        assert!(restored.suspended);
+
+        let buried = bury(&context, vec![restored]).unwrap();
+        let forgotten = forget(&context, buried.clone(), true).unwrap();
+        assert_eq!(buried[0].buried_until, forgotten[0].buried_until);
+    }
+
+    #[test]
+    fn set_due_picks_a_day_in_range() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        let now = Utc::now();
+        let days = config::load_day_boundary(collection, now).unwrap();
+        let today = days.day(now);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let scheduled = set_due(&context, cards, 3, Some(7), &mut rng).unwrap();
+
+        for card in &scheduled {
+            let offset = (days.day(card.due.unwrap()) - today).num_days();
+            assert!((3..=7).contains(&offset), "{} days out", offset);
+            assert_eq!(ReviewState::Learned, card.state);
+            assert_eq!(offset as f64, card.interval);
+        }
+        assert!(get_cards_to_review(&context, "testdeck").unwrap().is_empty());
+
+        let today_cards = set_due(&context, scheduled, 0, None, &mut rng).unwrap();
+        assert!(today_cards.iter().all(|card| card.interval >= 3.0));
+        assert_eq!(2, get_cards_to_review(&context, "testdeck").unwrap().len());
+    }
+
+    #[test]
+    fn reposition_only_moves_new_cards() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut cards = get_cards_to_review(&context, "testdeck").unwrap();
+        cards.reverse();
+        cards.push(lapsed_card(&context));
+
+        let moved = reposition(&context, cards.clone(), 10, 5).unwrap();
+
+        assert_eq!(2, moved.len());
+        assert_eq!(Some(10), moved[0].position);
+        assert_eq!(Some(15), moved[1].position);
+        let positions: Vec<Option<u32>> = get_deck_cards(&context, "testdeck")
+            .unwrap()
+            .into_iter()
+            .filter(|card| card.note_id == cards[0].note_id)
+            .map(|card| card.position)
+            .collect();
+        assert_eq!(vec![Some(10)], positions);
+    }
+
+    #[test]
+    fn new_cards_are_limited_per_day() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        fs::write(Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE), "new_per_day: 1\n").unwrap();
+        let context = Context::from(collection);
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(1, cards.len());
+
+        answer_card(&context, cards[0].clone(), ReviewScore::Easy, None, &DueCounts::new()).unwrap();
+
+        assert!(get_cards_to_review(&context, "testdeck").unwrap().is_empty());
+    }
+
+    #[test]
+    fn nested_decks_are_reviewed_with_their_parent() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let child = Path::new(collection).join("testdeck").join("child");
+        fs::create_dir_all(&child).unwrap();
+        for note_id in ["7", "8", "9"] {
+            fs::write(child.join(format!("{}_basic.md", note_id)), "# Front\nQ\n").unwrap();
+        }
+        fs::write(child.join(DECK_CONFIG_FILE), "new_per_day: 2\n").unwrap();
+        fs::write(Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE), "new_per_day: 3\n").unwrap();
+        let context = Context::from(collection);
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(vec!["7", "8", "123"], note_ids(&cards), "The child's own limit holds back its third card");
+        assert_eq!(vec!["7", "8"], note_ids(&get_cards_to_review(&context, "testdeck/child").unwrap()));
+    }
+
+    fn write_config(collection: &str, config: &str) {
+        fs::write(Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE), config).unwrap();
+    }
+
+    fn review_due(collection: &str, note_id: &str, days_ago: i64, interval: f64) {
+        fs::write(Path::new(collection).join("testdeck").join(format!("{}_basic.md", note_id)), "# Front\nQ\n").unwrap();
+        let learned = Review {
+            due: Some(Utc::now() - Duration::days(days_ago)),
+            interval,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let entry = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned);
+        append_entry(&get_review_path(collection, "testdeck", note_id), &entry).unwrap();
+    }
+
+    fn note_ids(cards: &[Card]) -> Vec<&str> {
+        cards.iter().map(|card| card.note_id.as_str()).collect()
+    }
+
+    #[test]
+    fn new_cards_in_creation_order_after_repositioned_ones() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        fs::write(Path::new(collection).join("testdeck").join("99_basic.md"), "# Front\nQ\n").unwrap();
+        let context = Context::from(collection);
+
+        assert_eq!(vec!["99", "123", "456"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+
+        write_config(collection, "new_order: filename\n");
+        assert_eq!(vec!["123", "456", "99"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+
+        let cards = get_cards_to_review(&context, "testdeck").unwrap();
+        reposition(&context, vec![cards[1].clone()], 0, 1).unwrap();
+        assert_eq!(vec!["456", "123", "99"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+
+        write_config(collection, "new_order: random\n");
+        let mut random = note_ids(&get_cards_to_review(&context, "testdeck").unwrap())
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            random,
+            note_ids(&get_cards_to_review(&context, "testdeck").unwrap()),
+            "Random order should hold for the day"
+        );
+        assert_eq!("456", random[0], "Repositioned cards should come first in random order too");
+        random.sort();
+        assert_eq!(vec!["123", "456", "99"], random);
+    }
+
+    #[test]
+    fn reviews_in_configured_order() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir_all(Path::new(collection).join("testdeck")).unwrap();
+        // 1 is the most overdue in days, 3 relative to its interval
+        review_due(collection, "1", 10, 100.0);
+        review_due(collection, "2", 5, 20.0);
+        review_due(collection, "3", 3, 2.0);
+        let context = Context::from(collection);
+
+        assert_eq!(vec!["1", "2", "3"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+
+        write_config(collection, "review_order: overdueness\n");
+        assert_eq!(vec!["3", "2", "1"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+
+        write_config(collection, "review_order: retrievability\n");
+        assert_eq!(vec!["3", "2", "1"], note_ids(&get_cards_to_review(&context, "testdeck").unwrap()));
+    }
+
+    #[test]
+    fn new_cards_mix_with_reviews() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir_all(Path::new(collection).join("testdeck")).unwrap();
+        for (days_ago, note_id) in ["4", "3", "2", "1"].iter().enumerate() {
+            review_due(collection, note_id, days_ago as i64 + 1, 10.0);
+        }
+        fs::write(Path::new(collection).join("testdeck").join("5_basic.md"), "# Front\nQ\n").unwrap();
+        let context = Context::from(collection);
+
+        let queue = get_cards_to_review(&context, "testdeck").unwrap();
+        assert_eq!(vec!["1", "2", "5", "3", "4"], note_ids(&queue));
+
+        write_config(collection, "new_mix: before\n");
+        assert_eq!("5", get_cards_to_review(&context, "testdeck").unwrap()[0].note_id);
+
+        write_config(collection, "new_mix: after\n");
+        assert_eq!("5", get_cards_to_review(&context, "testdeck").unwrap()[4].note_id);
+    }
+
+    #[test]
+    fn preview_shows_each_answer_without_logging() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let card = get_cards_to_review(&context, "testdeck").unwrap().remove(0);
+
+        assert_eq!(vec!["1m", "1m", "10m", "4d"], preview(&context, &card, Utc::now()).unwrap());
+        // Easy shows when the card will be due, as set by the deck
+        let deck_config = Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE);
+        fs::write(&deck_config, "scheduler:\n  easy_interval: 6\n").unwrap();
+        assert_eq!("6d", preview(&context, &card, Utc::now()).unwrap()[3]);
+        fs::remove_file(&deck_config).unwrap();
+
+        let learned = Card {
+            state: ReviewState::Learned,
+            due: Some(Utc::now()),
+            interval: 10.0,
+            ..card.clone()
+        };
+        assert_eq!(vec!["10m", "12d", "25d", "1.1mo"], preview(&context, &learned, Utc::now()).unwrap());
+        assert!(!get_review_path(collection, "testdeck", &card.note_id).exists());
+    }
+}
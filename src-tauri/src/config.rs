@@ -0,0 +1,605 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::deck;
+use chrono::{DateTime, Duration, Local, Offset, TimeZone, Utc};
+
+use crate::review::{self, Algorithm, DayBoundary, DueCounts, Fsrs, Review, ReviewScore, Scheduler, Sm2};
+
+pub const COLLECTION_CONFIG_FILE: &str = "collection.yaml";
+pub const DECK_CONFIG_FILE: &str = "deck.yaml";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub algorithm: Algorithm,
+    #[serde(flatten)]
+    pub sm2: Sm2,
+    pub fsrs: Fsrs,
+    pub fuzz: bool,
+    // Fuzz towards the day with the fewest cards due
+    pub load_balance: bool,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            algorithm: Algorithm::default(),
+            sm2: Sm2::default(),
+            fsrs: Fsrs::default(),
+            fuzz: true,
+            load_balance: true,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    pub fn scheduler(&self) -> Box<dyn Scheduler> {
+        match self.algorithm {
+            Algorithm::Sm2 => Box::new(self.sm2.clone()),
+            Algorithm::Fsrs => Box::new(Fsrs {
+                learning_steps: self.sm2.learning_steps.clone(),
+                relearning_steps: self.sm2.relearning_steps.clone(),
+                interval_modifier: self.sm2.interval_modifier,
+                maximum_interval: self.sm2.maximum_interval,
+                ..self.fsrs.clone()
+            }),
+        }
+    }
+
+    // Schedules a review with the configured algorithm, then fuzzes it with
+    // the given seed when fuzz is on
+    pub fn schedule(
+        &self,
+        review: Review,
+        time: DateTime<Utc>,
+        score: ReviewScore,
+        seed: u64,
+        due_counts: &DueCounts,
+        days: DayBoundary,
+    ) -> Review {
+        let review = self.scheduler().schedule(review, time, score);
+        let review = match (self.fuzz, self.load_balance) {
+            (true, true) => review::load_balance(review, seed, due_counts, days),
+            (true, false) => review::fuzz(review, seed),
+            (false, _) => review,
+        };
+        days.round_due(review::limit_interval(review, self.sm2.maximum_interval))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeechAction {
+    Tag,
+    Suspend,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NewOrder {
+    // When the note was created, from its id
+    Created,
+    Random,
+    Filename,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewOrder {
+    Due,
+    Random,
+    // Cards most likely to be forgotten first
+    Retrievability,
+    // Cards furthest past due relative to their interval first
+    Overdueness,
+}
+
+// Where cards go relative to the reviews
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueMix {
+    // Spread evenly through the reviews
+    Mixed,
+    Before,
+    After,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeckConfig {
+    pub scheduler: SchedulerConfig,
+    // Lapses after which a card is a leech
+    pub leech_threshold: u32,
+    pub leech_action: LeechAction,
+    // Bury the other cards of a note until tomorrow once one is answered
+    pub bury_new_siblings: bool,
+    pub bury_review_siblings: bool,
+    // Daily limits, shared with any decks nested inside this one
+    pub new_per_day: u32,
+    pub reviews_per_day: u32,
+    pub new_order: NewOrder,
+    pub review_order: ReviewOrder,
+    pub new_mix: QueueMix,
+    pub learning_mix: QueueMix,
+    // Longer answers are recorded as this long, as the user likely looked away
+    pub maximum_answer_seconds: u32,
+}
+
+impl Default for DeckConfig {
+    fn default() -> Self {
+        DeckConfig {
+            scheduler: SchedulerConfig::default(),
+            leech_threshold: 8,
+            leech_action: LeechAction::Tag,
+            bury_new_siblings: true,
+            bury_review_siblings: true,
+            new_per_day: 20,
+            reviews_per_day: 200,
+            new_order: NewOrder::Created,
+            review_order: ReviewOrder::Due,
+            new_mix: QueueMix::Mixed,
+            learning_mix: QueueMix::Before,
+            maximum_answer_seconds: 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GoalUnit {
+    Reviews,
+    Minutes,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DailyGoal {
+    pub unit: GoalUnit,
+    pub amount: u32,
+}
+
+// Settings that only make sense for the whole collection, read from
+// collection.yaml alongside the collection-wide deck config
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CollectionSettings {
+    // Minutes ahead of UTC, the system timezone when unset. This is a fixed
+    // offset, so it doesn't follow daylight saving time and the rollover
+    // drifts by an hour across the change. Leave it unset to follow the
+    // system timezone instead.
+    pub utc_offset_minutes: Option<i32>,
+    // Local hour at which one day ends and the next begins, from 0 to 23
+    pub rollover_hour: u32,
+    // Reviews or minutes of study to aim for each day, if any
+    pub daily_goal: Option<DailyGoal>,
+}
+
+impl Default for CollectionSettings {
+    fn default() -> Self {
+        CollectionSettings {
+            utc_offset_minutes: None,
+            rollover_hour: 4,
+            daily_goal: None,
+        }
+    }
+}
+
+impl CollectionSettings {
+    pub fn day_boundary(&self, time: DateTime<Utc>) -> DayBoundary {
+        let offset = match self.utc_offset_minutes {
+            Some(minutes) => Duration::minutes(minutes.into()),
+            None => Duration::seconds(
+                Local
+                    .offset_from_utc_datetime(&time.naive_utc())
+                    .fix()
+                    .local_minus_utc()
+                    .into(),
+            ),
+        };
+        DayBoundary::new(offset, self.rollover_hour)
+    }
+}
+
+// A missing file sets nothing, but one that can't be read or parsed is an
+// error rather than silently falling back to the defaults
+fn read_yaml(path: &Path) -> Result<Value, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_yaml::from_str(&contents).map_err(|err| format!("{}: {}", path.display(), err)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Value::Null),
+        Err(err) => Err(format!("{}: {}", path.display(), err)),
+    }
+}
+
+// Keys set in the override win, nested mappings are merged key by key
+fn merge_yaml(base: Value, overrides: Value) -> Value {
+    match (base, overrides) {
+        (Value::Mapping(mut base), Value::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                let merged = match base.remove(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Mapping(base)
+        }
+        (base, Value::Null) => base,
+        (_, overrides) => overrides,
+    }
+}
+
+// The keys a config file can set, with the defaults of nested settings
+fn known_keys<T: Serialize + Default>() -> Value {
+    serde_yaml::to_value(T::default()).unwrap_or(Value::Null)
+}
+
+// Serde ignores keys it doesn't know, so a misspelled one would silently do
+// nothing. `deny_unknown_fields` doesn't work alongside the flattened SM-2
+// settings, so keys are checked against the defaults instead.
+fn find_unknown_key(config: &Value, known: &Value) -> Option<String> {
+    let (config, known) = match (config, known) {
+        (Value::Mapping(config), Value::Mapping(known)) => (config, known),
+        _ => return None,
+    };
+    for (key, value) in config {
+        let name = key.as_str().map_or_else(|| format!("{:?}", key), str::to_string);
+        match known.get(key) {
+            Some(default) => {
+                if let Some(nested) = find_unknown_key(value, default) {
+                    return Some(format!("{}.{}", name, nested));
+                }
+            }
+            None => return Some(name),
+        }
+    }
+    None
+}
+
+fn check_keys(config: &Value, known: &Value, source: &str) -> Result<(), String> {
+    match find_unknown_key(config, known) {
+        Some(key) => Err(format!("{}: unknown setting `{}`", source, key)),
+        None => Ok(()),
+    }
+}
+
+// collection.yaml holds the collection settings as well as the deck config
+// every deck starts from
+fn read_collection_yaml(collection: &str) -> Result<Value, String> {
+    let path = Path::new(collection).join(COLLECTION_CONFIG_FILE);
+    let config = read_yaml(&path)?;
+    let known = merge_yaml(known_keys::<DeckConfig>(), known_keys::<CollectionSettings>());
+    check_keys(&config, &known, &path.display().to_string())?;
+    Ok(config)
+}
+
+// Names where the config came from in the error, as a wrong type shouldn't
+// quietly reset every setting
+fn parse_config<T: DeserializeOwned + Default>(config: Value, source: &str) -> Result<T, String> {
+    match config {
+        Value::Null => Ok(T::default()),
+        config => serde_yaml::from_value(config).map_err(|err| format!("{}: {}", source, err)),
+    }
+}
+
+pub fn load_collection_config(collection: &str) -> Result<DeckConfig, String> {
+    let path = Path::new(collection).join(COLLECTION_CONFIG_FILE);
+    parse_config(read_collection_yaml(collection)?, &path.display().to_string())
+}
+
+// A deck's deck.yaml overrides those of the decks it's nested in, inner ones
+// first, which override the collection-wide collection.yaml, which in turn
+// overrides the built in defaults.
+pub fn load_deck_config(collection: &str, deck: &str) -> Result<DeckConfig, String> {
+    let mut config = read_collection_yaml(collection)?;
+    let mut sources = vec![COLLECTION_CONFIG_FILE.to_string()];
+    let mut decks = deck::get_parent_decks(deck);
+    decks.push(deck.to_string());
+    for deck in decks {
+        let path = deck::get_deck_path(collection, &deck).join(DECK_CONFIG_FILE);
+        let deck_config = read_yaml(&path)?;
+        check_keys(&deck_config, &known_keys::<DeckConfig>(), &path.display().to_string())?;
+        if deck_config != Value::Null {
+            sources.insert(0, path.display().to_string());
+        }
+        config = merge_yaml(config, deck_config);
+    }
+
+    parse_config(config, &sources.join(" or "))
+}
+
+pub fn load_collection_settings(collection: &str) -> Result<CollectionSettings, String> {
+    let path = Path::new(collection).join(COLLECTION_CONFIG_FILE);
+    let settings: CollectionSettings = parse_config(read_collection_yaml(collection)?, &path.display().to_string())?;
+    if settings.rollover_hour > 23 {
+        return Err(format!(
+            "{}: rollover_hour must be from 0 to 23, found {}",
+            path.display(),
+            settings.rollover_hour
+        ));
+    }
+    Ok(settings)
+}
+
+// The day boundary in effect at the given time
+pub fn load_day_boundary(collection: &str, time: DateTime<Utc>) -> Result<DayBoundary, String> {
+    Ok(load_collection_settings(collection)?.day_boundary(time))
+}
+
+fn get_config_path(collection: &str, deck: Option<&str>) -> PathBuf {
+    match deck {
+        Some(deck) => deck::get_deck_path(collection, deck).join(DECK_CONFIG_FILE),
+        None => Path::new(collection).join(COLLECTION_CONFIG_FILE),
+    }
+}
+
+// Merges the given values into deck.yaml, or collection.yaml when no deck is
+// given, keeping whatever else the file already sets.
+pub fn update_config(collection: &str, deck: Option<&str>, overrides: Value) -> Result<(), String> {
+    let path = get_config_path(collection, deck);
+    let merged = merge_yaml(read_yaml(&path)?, overrides);
+
+    match serde_yaml::to_string(&merged) {
+        Ok(contents) => fs::write(path, contents).map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub fn save_fsrs_weights(collection: &str, deck: Option<&str>, weights: &[f64]) -> Result<(), String> {
+    let mut fsrs = Mapping::new();
+    fsrs.insert("weights".into(), serde_yaml::to_value(weights).map_err(|err| err.to_string())?);
+    let mut scheduler = Mapping::new();
+    scheduler.insert("fsrs".into(), Value::Mapping(fsrs));
+    let mut overrides = Mapping::new();
+    overrides.insert("scheduler".into(), Value::Mapping(scheduler));
+
+    update_config(collection, deck, Value::Mapping(overrides))
+}
+
+// Sets the daily goal in collection.yaml, or removes it when none is given
+pub fn save_daily_goal(collection: &str, goal: Option<&DailyGoal>) -> Result<(), String> {
+    match goal {
+        Some(goal) => {
+            let mut overrides = Mapping::new();
+            overrides.insert("daily_goal".into(), serde_yaml::to_value(goal).map_err(|err| err.to_string())?);
+            update_config(collection, None, Value::Mapping(overrides))
+        }
+        None => {
+            let path = get_config_path(collection, None);
+            let mut config = match read_yaml(&path)? {
+                Value::Mapping(config) => config,
+                _ => return Ok(()),
+            };
+            config.remove("daily_goal");
+            match serde_yaml::to_string(&config) {
+                Ok(contents) => fs::write(path, contents).map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        load_collection_config, load_collection_settings, load_day_boundary, load_deck_config, save_daily_goal,
+        save_fsrs_weights, DailyGoal, GoalUnit, SchedulerConfig, COLLECTION_CONFIG_FILE, DECK_CONFIG_FILE,
+    };
+    use crate::review::{score_card, Algorithm, DayBoundary, DueCounts, Review, ReviewScore, ReviewState, Sm2};
+    use chrono::{Duration, Utc};
+    use std::fs;
+
+    #[test]
+    fn deck_config_defaults() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+
+        let config = load_deck_config(collection, "testdeck").unwrap();
+
+        assert_eq!(Algorithm::Sm2, config.scheduler.algorithm);
+        assert_eq!(Sm2::default(), config.scheduler.sm2);
+    }
+
+    #[test]
+    fn deck_config_overrides_collection_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+        fs::write(
+            tempdir.path().join(COLLECTION_CONFIG_FILE),
+            "scheduler:\n  algorithm: fsrs\n  learning_steps: [5]\n  easy_bonus: 1.5\n",
+        )
+        .unwrap();
+        fs::write(
+            tempdir.path().join("testdeck").join(DECK_CONFIG_FILE),
+            "scheduler:\n  learning_steps: [1, 10, 60]\n  relearning_steps: []\n  fsrs:\n    desired_retention: 0.85\n",
+        )
+        .unwrap();
+
+        let config = load_deck_config(collection, "testdeck").unwrap().scheduler;
+
+        assert_eq!(Algorithm::Fsrs, config.algorithm);
+        assert_eq!(vec![1, 10, 60], config.sm2.learning_steps);
+        assert_eq!(Vec::<u32>::new(), config.sm2.relearning_steps);
+        assert_eq!(1.5, config.sm2.easy_bonus);
+        assert_eq!(Sm2::default().minimum_ease, config.sm2.minimum_ease);
+        assert_eq!(0.85, config.fsrs.desired_retention);
+
+        let other = load_deck_config(collection, "otherdeck").unwrap().scheduler;
+        assert_eq!(vec![5], other.sm2.learning_steps);
+        assert_eq!(0.9, other.fsrs.desired_retention);
+    }
+
+    #[test]
+    fn invalid_config_is_an_error() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+        fs::write(tempdir.path().join("testdeck").join(DECK_CONFIG_FILE), "new_per_day: lots\n").unwrap();
+        fs::write(tempdir.path().join(COLLECTION_CONFIG_FILE), "rollover_hour: [4\n").unwrap();
+
+        let err = load_deck_config(collection, "testdeck").unwrap_err();
+        assert!(err.contains(COLLECTION_CONFIG_FILE), "{}", err);
+        assert!(load_collection_settings(collection).is_err());
+        assert!(save_fsrs_weights(collection, None, &[1.0]).is_err());
+        assert_eq!("rollover_hour: [4\n", fs::read_to_string(tempdir.path().join(COLLECTION_CONFIG_FILE)).unwrap());
+
+        fs::write(tempdir.path().join(COLLECTION_CONFIG_FILE), "rollover_hour: 4\n").unwrap();
+        let err = load_deck_config(collection, "testdeck").unwrap_err();
+        assert!(err.contains(DECK_CONFIG_FILE), "{}", err);
+    }
+
+    #[test]
+    fn nested_decks_inherit_parent_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        let parent = tempdir.path().join("parent");
+        fs::create_dir_all(parent.join("child").join("grandchild")).unwrap();
+        fs::write(tempdir.path().join(COLLECTION_CONFIG_FILE), "new_per_day: 5\nleech_threshold: 4\n").unwrap();
+        fs::write(parent.join(DECK_CONFIG_FILE), "new_per_day: 10\nreviews_per_day: 50\n").unwrap();
+        fs::write(parent.join("child").join(DECK_CONFIG_FILE), "reviews_per_day: 30\n").unwrap();
+
+        let config = load_deck_config(collection, "parent/child/grandchild").unwrap();
+        assert_eq!(10, config.new_per_day);
+        assert_eq!(30, config.reviews_per_day);
+        assert_eq!(4, config.leech_threshold);
+        assert_eq!(50, load_deck_config(collection, "parent").unwrap().reviews_per_day);
+
+        fs::write(parent.join(DECK_CONFIG_FILE), "new_per_day: ten\n").unwrap();
+        let err = load_deck_config(collection, "parent/child").unwrap_err();
+        assert!(err.contains(&parent.join(DECK_CONFIG_FILE).display().to_string()), "{}", err);
+    }
+
+    #[test]
+    fn misspelled_keys_are_an_error() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+        let deck_config = tempdir.path().join("testdeck").join(DECK_CONFIG_FILE);
+        let collection_config = tempdir.path().join(COLLECTION_CONFIG_FILE);
+        fs::write(&deck_config, "new_per_dya: 5\n").unwrap();
+        fs::write(&collection_config, "rollover_hour: 4\nscheduler:\n  algoritm: fsrs\n").unwrap();
+
+        let err = load_collection_settings(collection).unwrap_err();
+        assert!(err.contains("`scheduler.algoritm`"), "{}", err);
+
+        fs::write(&collection_config, "rollover_hour: 4\nscheduler:\n  algorithm: fsrs\n").unwrap();
+        assert!(load_collection_config(collection).is_ok(), "Collection settings and deck config share the file");
+        let err = load_deck_config(collection, "testdeck").unwrap_err();
+        assert!(err.contains(DECK_CONFIG_FILE) && err.contains("`new_per_dya`"), "{}", err);
+
+        fs::write(&deck_config, "rollover_hour: 5\n").unwrap();
+        assert!(load_deck_config(collection, "testdeck").is_err(), "The day boundary is set for the whole collection");
+    }
+
+    #[test]
+    fn easy_interval_sets_when_easy_cards_are_due() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+        fs::write(tempdir.path().join("testdeck").join(DECK_CONFIG_FILE), "scheduler:\n  easy_interval: 7\n").unwrap();
+        let time = Utc::now();
+
+        let scheduler = load_deck_config(collection, "testdeck").unwrap().scheduler.scheduler();
+        let review = scheduler.schedule(Review::default(), time, ReviewScore::Easy);
+
+        assert_eq!(7.0, review.interval);
+        assert_eq!(Some(time + Duration::days(7)), review.due);
+    }
+
+    #[test]
+    fn interval_modifier_applies_to_fsrs() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            stability: Some(10.0),
+            difficulty: Some(5.0),
+            ..Review::default()
+        };
+        let config = |interval_modifier: f64| SchedulerConfig {
+            algorithm: Algorithm::Fsrs,
+            sm2: Sm2 { interval_modifier, ..Sm2::default() },
+            ..SchedulerConfig::default()
+        };
+
+        let full = config(1.0).scheduler().schedule(card.clone(), time, ReviewScore::Good);
+        let halved = config(0.5).scheduler().schedule(card, time, ReviewScore::Good);
+
+        assert_eq!(full.stability, halved.stability);
+        assert_eq!((full.interval / 2.0).round(), halved.interval);
+    }
+
+    #[test]
+    fn scheduler_config_fuzz() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 20.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let days = DayBoundary::default();
+        let exact = days.round_due(score_card(card.clone(), time, ReviewScore::Good));
+
+        let unfuzzed = SchedulerConfig { fuzz: false, ..SchedulerConfig::default() };
+        assert_eq!(exact, unfuzzed.schedule(card.clone(), time, ReviewScore::Good, 7, &DueCounts::new(), days));
+
+        let unbalanced = SchedulerConfig { load_balance: false, ..SchedulerConfig::default() };
+        let fuzzed = unbalanced.schedule(card.clone(), time, ReviewScore::Good, 7, &DueCounts::new(), days);
+        assert_ne!(exact.interval, fuzzed.interval);
+
+        let balanced =
+            SchedulerConfig::default().schedule(card, time, ReviewScore::Good, 7, &DueCounts::new(), days);
+        assert_eq!(0.0, (balanced.interval - exact.interval).fract());
+    }
+
+    #[test]
+    fn collection_settings_day_boundary() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        assert_eq!(4, load_collection_settings(collection).unwrap().rollover_hour);
+
+        fs::write(
+            tempdir.path().join(COLLECTION_CONFIG_FILE),
+            "utc_offset_minutes: -300\nrollover_hour: 2\nleech_threshold: 4\n",
+        )
+        .unwrap();
+
+        let settings = load_collection_settings(collection).unwrap();
+        assert_eq!(Some(-300), settings.utc_offset_minutes);
+        assert_eq!(DayBoundary::new(Duration::hours(-5), 2), settings.day_boundary(Utc::now()));
+        assert_eq!(4, load_deck_config(collection, "testdeck").unwrap().leech_threshold);
+
+        fs::write(tempdir.path().join(COLLECTION_CONFIG_FILE), "rollover_hour: 24\n").unwrap();
+        let err = load_day_boundary(collection, Utc::now()).unwrap_err();
+        assert!(err.contains("rollover_hour"), "{}", err);
+    }
+
+    #[test]
+    fn daily_goal_is_saved_and_removed() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::write(tempdir.path().join(COLLECTION_CONFIG_FILE), "rollover_hour: 2\n").unwrap();
+        let goal = DailyGoal {
+            unit: GoalUnit::Minutes,
+            amount: 15,
+        };
+
+        save_daily_goal(collection, Some(&goal)).unwrap();
+        let settings = load_collection_settings(collection).unwrap();
+        assert_eq!(Some(goal), settings.daily_goal);
+        assert_eq!(2, settings.rollover_hour);
+
+        save_daily_goal(collection, None).unwrap();
+        let settings = load_collection_settings(collection).unwrap();
+        assert_eq!(None, settings.daily_goal);
+        assert_eq!(2, settings.rollover_hour);
+    }
+}
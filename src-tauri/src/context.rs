@@ -6,13 +6,12 @@ pub struct Context {
 
 impl From<&str> for Context {
     fn from(collection_path: &str) -> Self {
-        Context { collection_path: Path::from(collection_path).to_path_buf() }
+        Context { collection_path: Path::new(collection_path).to_path_buf() }
     }
 }
 
 impl Context {
     pub fn get_collection_path(&self) -> String {
-        self.collection_path
+        self.collection_path.to_string_lossy().to_string()
     }
 }
-
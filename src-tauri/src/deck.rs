@@ -14,21 +14,58 @@ pub fn get_deck_path(collection: &str, deck: &str) -> PathBuf {
 fn get_decks_from_paths(paths: ReadDir) -> Vec<String> {
     paths
         .map(|path| match path {
-            Ok(p) => Some(p.path().file_stem().unwrap().to_str().unwrap().to_string()),
+            Ok(p) => Some(p),
             Err(_) => None,
         })
         .filter(|x| x.is_some())
         .map(|x| x.unwrap())
+        // The collection folder also holds collection.yaml
+        .filter(|x| match x.file_type() {
+            Ok(t) => t.is_dir(),
+            Err(_) => false,
+        })
+        .map(|p| p.path().file_stem().unwrap().to_str().unwrap().to_string())
         .collect()
 }
 
-fn get_decks(context: &Context) -> Result<Vec<String>, String> {
+pub fn get_decks(context: &Context) -> Result<Vec<String>, String> {
     match fs::read_dir(context.get_collection_path()) {
         Ok(paths) => Ok(get_decks_from_paths(paths)),
         Err(err) => Err(err.to_string()),
     }
 }
 
+// Decks can be nested as folders inside a deck, with ids like "parent/child".
+// Returns the deck itself followed by every deck nested inside it.
+pub fn get_deck_tree(collection: &str, deck: &str) -> Vec<String> {
+    let mut decks = vec![deck.to_string()];
+    if let Ok(paths) = fs::read_dir(get_deck_path(collection, deck)) {
+        for child in get_decks_from_paths(paths) {
+            // The reviews folder holds the deck's review logs
+            if child != "reviews" {
+                decks.extend(get_deck_tree(collection, &format!("{}/{}", deck, child)));
+            }
+        }
+    }
+    decks
+}
+
+// The given deck, or every deck in the collection when none is given, along
+// with the decks nested in them
+pub fn get_deck_scope(context: &Context, deck: Option<&str>) -> Result<Vec<String>, String> {
+    let decks = match deck {
+        Some(deck) => vec![deck.to_string()],
+        None => get_decks(context)?,
+    };
+    let collection = context.get_collection_path();
+    Ok(decks.iter().flat_map(|deck| get_deck_tree(&collection, deck)).collect())
+}
+
+// The decks a nested deck sits in, outermost first
+pub fn get_parent_decks(deck: &str) -> Vec<String> {
+    deck.match_indices('/').map(|(index, _)| deck[..index].to_string()).collect()
+}
+
 #[tauri::command]
 pub fn get_decks_handler(state: State<'_, Context>) -> Result<Vec<String>, String> {
     match fs::read_dir(state.inner().get_collection_path()) {
@@ -54,7 +91,6 @@ mod tests {
     use crate::{deck, context::Context};
     use tempfile::{tempdir, TempDir};
     use std::fs;
-    use tauri::State;
 
     fn scaffold_collection() -> TempDir {
         tempdir().unwrap()
@@ -63,10 +99,10 @@ mod tests {
     #[test]
     fn create_deck() {
         let collection_path = scaffold_collection();
-        deck::create_deck(&Context { collection_path: collection_path.into_path() }, "testdeck");
+        deck::create_deck(&Context::from(collection_path.path().to_str().unwrap()), "testdeck");
 
         assert!(
-            fs::read_dir(collection_path)
+            fs::read_dir(collection_path.path())
                 .unwrap()
                 .all(|paths| "testdeck" == paths.unwrap().file_name().to_str().unwrap()),
             "There should only be one deck (folder) in the collection (folder) with name testdeck"
@@ -76,15 +112,14 @@ mod tests {
     #[test]
     fn list_decks() {
         let collection_path = scaffold_collection();
-        let context = Context { collection_path: collection_path.into_path() };
+        let context = Context::from(collection_path.path().to_str().unwrap());
         deck::create_deck(&context, "testdeck");
 
-        let state = tauri::Manager::manage(context);
+        let decks = deck::get_decks(&context).unwrap();
 
-        let decks = deck::get_decks_handler(context.into()).unwrap();
-
-        assert!(
-            decks.into_iter().all(|deck| "testdeck" == deck),
+        assert_eq!(
+            vec!["testdeck".to_string()],
+            decks,
             "There should only be one deck (folder) in the collection (folder) with name testdeck"
         )
     }
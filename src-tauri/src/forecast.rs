@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use tauri::State;
+
+use crate::config;
+use crate::context::Context;
+use crate::deck;
+use crate::history;
+use crate::review::{DayBoundary, Review, ReviewState};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DueCards {
+    pub learning: usize,
+    pub review: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ForecastDay {
+    pub day: NaiveDate,
+    pub due: DueCards,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Forecast {
+    // Due before today and not yet reviewed
+    pub overdue: DueCards,
+    // Today first
+    pub days: Vec<ForecastDay>,
+}
+
+// The latest state of every card with a review log in the given decks
+fn get_reviews(collection: &str, decks: &[String]) -> Vec<Review> {
+    decks
+        .iter()
+        .flat_map(|deck| history::read_deck_logs(collection, deck).into_values())
+        .flat_map(|entries| history::latest_entries(entries).into_values())
+        .map(|entry| entry.after)
+        .collect()
+}
+
+// Counts the cards coming due on each of the next `days` days. Suspended cards
+// are left out, and buried cards count from when they come back.
+pub fn get_forecast(
+    reviews: &[Review],
+    boundary: DayBoundary,
+    time: DateTime<Utc>,
+    days: u32,
+) -> Forecast {
+    let today = boundary.day(time);
+    let mut forecast = Forecast {
+        overdue: DueCards::default(),
+        days: (0..days)
+            .map(|offset| ForecastDay {
+                day: today + Duration::days(offset.into()),
+                due: DueCards::default(),
+            })
+            .collect(),
+    };
+
+    for review in reviews.iter().filter(|review| !review.suspended) {
+        let due = match (review.due, review.buried_until) {
+            (Some(due), Some(until)) => due.max(until),
+            (Some(due), None) => due,
+            (None, _) => continue,
+        };
+        let day = boundary.day(due);
+        let counts = if day < today {
+            &mut forecast.overdue
+        } else {
+            match forecast.days.get_mut((day - today).num_days() as usize) {
+                Some(forecast_day) => &mut forecast_day.due,
+                None => continue,
+            }
+        };
+        match review.state {
+            ReviewState::Learned => counts.review += 1,
+            _ => counts.learning += 1,
+        }
+    }
+
+    forecast
+}
+
+// Forecasts a deck, or the whole collection when no deck is given
+pub fn forecast_workload(
+    context: &Context,
+    deck: Option<&str>,
+    days: u32,
+) -> Result<Forecast, String> {
+    let collection = context.get_collection_path();
+    let reviews = get_reviews(&collection, &deck::get_deck_scope(context, deck)?);
+    let time = Utc::now();
+    Ok(get_forecast(
+        &reviews,
+        config::load_day_boundary(&collection, time)?,
+        time,
+        days,
+    ))
+}
+
+#[tauri::command]
+pub fn forecast(
+    state: State<'_, Context>,
+    deck: Option<String>,
+    days: u32,
+) -> Result<Forecast, String> {
+    forecast_workload(state.inner(), deck.as_deref(), days)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::COLLECTION_CONFIG_FILE;
+    use crate::context::Context;
+    use crate::forecast::{forecast_workload, DueCards};
+    use crate::review::{Review, ReviewState};
+    use crate::test_helpers::{answer, learned};
+    use chrono::{Duration, Utc};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn forecast_splits_learning_and_reviews() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        // Days start at midnight UTC, so the offsets below land on known days
+        fs::write(Path::new(collection).join(COLLECTION_CONFIG_FILE), "utc_offset_minutes: 0\nrollover_hour: 0\n").unwrap();
+        fs::create_dir_all(Path::new(collection).join("first").join("nested")).unwrap();
+        fs::create_dir_all(Path::new(collection).join("second")).unwrap();
+
+        answer(collection, "first", "1", Review::default(), learned(-3), Utc::now());
+        answer(collection, "first", "2", Review::default(), learned(1), Utc::now());
+        answer(collection, "first/nested", "3", Review::default(), learned(1), Utc::now());
+        let relearning = Review { due: Some(Utc::now() - Duration::days(2)), state: ReviewState::Relearning, ..Review::default() };
+        answer(collection, "first", "4", Review::default(), relearning, Utc::now());
+        answer(collection, "first", "5", Review::default(), Review { suspended: true, ..learned(1) }, Utc::now());
+        answer(collection, "second", "6", Review::default(), learned(2), Utc::now());
+        answer(collection, "second", "7", Review::default(), learned(30), Utc::now());
+        let context = Context::from(collection);
+
+        let forecast = forecast_workload(&context, Some("first"), 3).unwrap();
+        assert_eq!(DueCards { learning: 1, review: 1 }, forecast.overdue);
+        assert_eq!(3, forecast.days.len());
+        assert_eq!(DueCards { learning: 0, review: 2 }, forecast.days[1].due);
+
+        let forecast = forecast_workload(&context, None, 3).unwrap();
+        assert_eq!(DueCards { learning: 0, review: 1 }, forecast.days[2].due);
+        let total: usize = forecast.days.iter().map(|day| day.due.learning + day.due.review).sum();
+        assert_eq!(5, total + forecast.overdue.learning + forecast.overdue.review);
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Utc};
+
+use crate::deck;
+use crate::review::{Review, ReviewScore, ReviewState};
+
+// 2: manual and undo entries, so score is optional
+pub const LOG_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EntryKind {
+    // The card was answered
+    #[default]
+    Review,
+    // The card was changed by hand, e.g. suspended or buried
+    Manual,
+    // Tombstone for the card's latest entry that hasn't been undone yet
+    Undo,
+    // The card was reset to New and its earlier entries no longer count
+    Forget,
+}
+
+// One line of reviews/<note_id>.jsonl
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReviewLogEntry {
+    pub version: u32,
+    pub card_num: u32,
+    pub reviewed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub kind: EntryKind,
+    pub score: Option<ReviewScore>,
+    pub before: Review,
+    pub after: Review,
+    #[serde(default)]
+    pub answer_millis: Option<u64>,
+}
+
+impl ReviewLogEntry {
+    pub fn new(
+        card_num: u32,
+        reviewed_at: DateTime<Utc>,
+        score: ReviewScore,
+        before: Review,
+        after: Review,
+    ) -> Self {
+        ReviewLogEntry {
+            version: LOG_VERSION,
+            card_num,
+            reviewed_at,
+            kind: EntryKind::Review,
+            score: Some(score),
+            before,
+            after,
+            answer_millis: None,
+        }
+    }
+
+    pub fn manual(card_num: u32, reviewed_at: DateTime<Utc>, before: Review, after: Review) -> Self {
+        ReviewLogEntry {
+            version: LOG_VERSION,
+            card_num,
+            reviewed_at,
+            kind: EntryKind::Manual,
+            score: None,
+            before,
+            after,
+            answer_millis: None,
+        }
+    }
+
+    // Whether this is the first time the card was answered
+    pub fn is_first_answer(&self) -> bool {
+        self.kind == EntryKind::Review && self.before.state == ReviewState::New && self.before.due.is_none()
+    }
+
+    pub fn undo(undone: &ReviewLogEntry, reviewed_at: DateTime<Utc>) -> Self {
+        ReviewLogEntry {
+            kind: EntryKind::Undo,
+            ..ReviewLogEntry::manual(undone.card_num, reviewed_at, undone.after.clone(), undone.before.clone())
+        }
+    }
+}
+
+// Before entries were versioned, the log was a run of card snapshots
+// written back to back without newlines or timestamps.
+#[derive(Deserialize)]
+struct LegacySnapshot {
+    card_num: u32,
+    interval: f64,
+    due: Option<DateTime<Utc>>,
+    ease: f64,
+    state: ReviewState,
+    steps: u32,
+    score: ReviewScore,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LogLine {
+    Entry(ReviewLogEntry),
+    Legacy(LegacySnapshot),
+}
+
+fn legacy_epoch() -> DateTime<Utc> {
+    DateTime::<Utc>::from(std::time::SystemTime::UNIX_EPOCH)
+}
+
+pub fn parse_log(bytes: &[u8]) -> Vec<ReviewLogEntry> {
+    parse_log_lines(bytes).0
+}
+
+// Also returns the numbers of the lines that couldn't be read, counting from 1
+pub fn parse_log_lines(bytes: &[u8]) -> (Vec<ReviewLogEntry>, Vec<usize>) {
+    let mut previous: BTreeMap<u32, Review> = BTreeMap::new();
+    let mut entries = vec![];
+    let mut unreadable = vec![];
+
+    // A line that can't be read, e.g. one left half written by a crash, is
+    // skipped so the entries after it still count. Legacy snapshots all sit
+    // on one line.
+    for (number, line) in bytes.split(|byte| *byte == b'\n').enumerate() {
+        for value in serde_json::Deserializer::from_slice(line).into_iter::<LogLine>() {
+            let entry = match value {
+                Ok(LogLine::Entry(entry)) => entry,
+                // Legacy snapshots have no timestamp, so they are dated to the epoch
+                // and only their order in the file is meaningful.
+                Ok(LogLine::Legacy(snapshot)) => ReviewLogEntry {
+                    version: 0,
+                    ..ReviewLogEntry::new(
+                        snapshot.card_num,
+                        legacy_epoch(),
+                        snapshot.score,
+                        previous
+                            .get(&snapshot.card_num)
+                            .cloned()
+                            .unwrap_or_default(),
+                        Review::new(
+                            snapshot.due,
+                            snapshot.interval,
+                            snapshot.ease,
+                            snapshot.state,
+                            snapshot.steps,
+                        ),
+                    )
+                },
+                Err(_) => {
+                    unreadable.push(number + 1);
+                    break;
+                }
+            };
+            previous.insert(entry.card_num, entry.after.clone());
+            entries.push(entry);
+        }
+    }
+
+    (entries, unreadable)
+}
+
+pub fn read_log(path: &Path) -> Vec<ReviewLogEntry> {
+    match fs::read(path) {
+        Ok(bytes) => parse_log(&bytes),
+        Err(_) => vec![],
+    }
+}
+
+pub fn get_reviews_path(collection: &str, deck: &str) -> PathBuf {
+    deck::get_deck_path(collection, deck).join("reviews")
+}
+
+// The path of every review log in a deck, keyed by note id
+fn get_log_paths(collection: &str, deck: &str) -> BTreeMap<String, PathBuf> {
+    match fs::read_dir(get_reviews_path(collection, deck)) {
+        Ok(paths) => paths
+            .filter_map(|path| path.ok())
+            .map(|path| path.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+            .collect(),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+// Every review log in a deck, keyed by note id
+pub fn read_deck_logs(collection: &str, deck: &str) -> BTreeMap<String, Vec<ReviewLogEntry>> {
+    get_log_paths(collection, deck)
+        .into_iter()
+        .map(|(note_id, path)| (note_id, read_log(&path)))
+        .collect()
+}
+
+// The lines that were skipped because they couldn't be read, e.g. ones left
+// half written by a crash, keyed by the log's path within the collection
+pub fn find_unreadable_lines(collection: &str, deck: &str) -> BTreeMap<String, Vec<usize>> {
+    get_log_paths(collection, deck)
+        .into_iter()
+        .filter_map(|(note_id, path)| {
+            let (_, unreadable) = parse_log_lines(&fs::read(path).ok()?);
+            if unreadable.is_empty() {
+                return None;
+            }
+            Some((format!("{}/reviews/{}.jsonl", deck, note_id), unreadable))
+        })
+        .collect()
+}
+
+pub fn append_entry(path: &Path, entry: &ReviewLogEntry) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+
+    let mut line = serde_json::to_string(entry).map_err(|err| err.to_string())?;
+    line.push('\n');
+    // Starts a new line if a crash left the last one unfinished
+    if let Ok(bytes) = fs::read(path) {
+        if bytes.last().is_some_and(|byte| *byte != b'\n') {
+            line.insert(0, '\n');
+        }
+    }
+
+    match fs::OpenOptions::new().append(true).create(true).open(path) {
+        Ok(mut file) => file.write_all(line.as_bytes()).map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Drops every entry that has been undone, along with the undo entries
+// themselves. Each undo takes back the latest remaining entry of its card, so
+// undoing repeatedly steps further back. Entries before a card's last forget
+// are dropped as well.
+pub fn effective_entries(entries: Vec<ReviewLogEntry>) -> Vec<ReviewLogEntry> {
+    let mut effective: Vec<ReviewLogEntry> = vec![];
+    for entry in entries {
+        if entry.kind != EntryKind::Undo {
+            effective.push(entry);
+        } else if let Some(index) = effective.iter().rposition(|x| x.card_num == entry.card_num) {
+            effective.remove(index);
+        }
+    }
+
+    let forgotten: BTreeMap<u32, usize> = effective
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.kind == EntryKind::Forget)
+        .map(|(index, entry)| (entry.card_num, index))
+        .collect();
+    effective
+        .into_iter()
+        .enumerate()
+        .filter(|(index, entry)| forgotten.get(&entry.card_num).map_or(true, |forget| index >= forget))
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+// The last entry for each card_num holds that card's current state
+pub fn latest_entries(entries: Vec<ReviewLogEntry>) -> BTreeMap<u32, ReviewLogEntry> {
+    effective_entries(entries)
+        .into_iter()
+        .map(|entry| (entry.card_num, entry))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{
+        append_entry, effective_entries, find_unreadable_lines, latest_entries, parse_log, read_log, EntryKind,
+        ReviewLogEntry, LOG_VERSION,
+    };
+    use crate::review::{Review, ReviewScore, ReviewState};
+    use crate::test_helpers::learned;
+    use chrono::Utc;
+
+    #[test]
+    fn entries_are_newline_delimited() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("reviews").join("123.jsonl");
+        let first = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let second = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, learned(1), learned(3));
+
+        append_entry(&path, &first).unwrap();
+        append_entry(&path, &second).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(2, contents.lines().count(), "Each entry should be on its own line");
+        assert_eq!(vec![first, second], read_log(&path));
+    }
+
+    #[test]
+    fn unreadable_lines_are_skipped() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("testdeck").join("reviews").join("123.jsonl");
+        let first = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let second = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, learned(1), learned(3));
+        let third = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, learned(3), learned(8));
+
+        append_entry(&path, &first).unwrap();
+        let half_written = serde_json::to_string(&second).unwrap();
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str(&half_written[..half_written.len() / 2]);
+        std::fs::write(&path, contents).unwrap();
+        append_entry(&path, &second).unwrap();
+        append_entry(&path, &third).unwrap();
+
+        assert_eq!(vec![first, second, third], read_log(&path));
+        let unreadable = find_unreadable_lines(tempdir.path().to_str().unwrap(), "testdeck");
+        assert_eq!(vec![2], unreadable["testdeck/reviews/123.jsonl"]);
+    }
+
+    #[test]
+    fn legacy_snapshots_are_parsed() {
+        let legacy = concat!(
+            r#"{"note_id":"123","deck_id":"deck","card_num":1,"interval":1.0,"due":"2023-01-01T00:10:00Z","ease":2.5,"state":"New","steps":1,"template":"basic","score":"Good"}"#,
+            r#"{"note_id":"123","deck_id":"deck","card_num":1,"interval":1.0,"due":"2023-01-02T00:10:00Z","ease":2.5,"state":"Learned","steps":0,"template":"basic","score":"Good"}"#
+        );
+
+        let entries = parse_log(legacy.as_bytes());
+
+        assert_eq!(2, entries.len());
+        assert!(entries.iter().all(|entry| entry.version == 0));
+        assert_eq!(ReviewState::New, entries[0].before.state);
+        assert_eq!(entries[0].after, entries[1].before);
+        assert_eq!(ReviewState::Learned, entries[1].after.state);
+    }
+
+    #[test]
+    fn latest_entry_per_card() {
+        let entries = vec![
+            ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned(1)),
+            ReviewLogEntry::new(2, Utc::now(), ReviewScore::Again, Review::default(), Review::default()),
+            ReviewLogEntry::new(1, Utc::now(), ReviewScore::Easy, learned(1), learned(4)),
+        ];
+
+        let latest = latest_entries(entries);
+
+        assert_eq!(2, latest.len());
+        assert_eq!(Some(ReviewScore::Easy), latest[&1].score);
+        assert_eq!(LOG_VERSION, latest[&2].version);
+    }
+
+    #[test]
+    fn version_one_entries_are_reviews() {
+        let line = r#"{"version":1,"card_num":1,"reviewed_at":"2023-01-01T00:00:00Z","score":"Hard","before":{"due":null,"interval":1.0,"ease":2.5,"state":"New","steps":0,"stability":null,"difficulty":null},"after":{"due":"2023-01-01T00:01:00Z","interval":1.0,"ease":2.5,"state":"New","steps":0,"stability":null,"difficulty":null},"answer_millis":null}"#;
+
+        let entries = parse_log(line.as_bytes());
+
+        assert_eq!(1, entries.len());
+        assert_eq!(EntryKind::Review, entries[0].kind);
+        assert_eq!(Some(ReviewScore::Hard), entries[0].score);
+    }
+
+    #[test]
+    fn manual_entries_have_no_score() {
+        let suspended = Review {
+            suspended: true,
+            ..Review::default()
+        };
+        let entry = ReviewLogEntry::manual(1, Utc::now(), Review::default(), suspended.clone());
+
+        let entries = parse_log(serde_json::to_string(&entry).unwrap().as_bytes());
+
+        assert_eq!(vec![entry], entries);
+        assert_eq!(EntryKind::Manual, entries[0].kind);
+        assert_eq!(None, entries[0].score);
+        assert_eq!(suspended, latest_entries(entries)[&1].after);
+    }
+
+    #[test]
+    fn undo_entries_step_back_one_entry_each() {
+        let first = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let second = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Again, learned(1), Review::default());
+        let other = ReviewLogEntry::new(2, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let mut entries = vec![first.clone(), other.clone(), second.clone()];
+
+        entries.push(ReviewLogEntry::undo(&second, Utc::now()));
+        assert_eq!(vec![first.clone(), other.clone()], effective_entries(entries.clone()));
+        assert_eq!(first, latest_entries(entries.clone())[&1]);
+
+        entries.push(ReviewLogEntry::undo(&first, Utc::now()));
+        assert_eq!(vec![other.clone()], effective_entries(entries.clone()));
+        assert!(!latest_entries(entries.clone()).contains_key(&1));
+
+        // Nothing left to undo for card 1, so this is a no-op
+        entries.push(ReviewLogEntry::undo(&first, Utc::now()));
+        assert_eq!(vec![other], effective_entries(entries));
+    }
+
+    #[test]
+    fn forget_drops_earlier_entries_until_undone() {
+        let first = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let other = ReviewLogEntry::new(2, Utc::now(), ReviewScore::Good, Review::default(), learned(1));
+        let forget = ReviewLogEntry {
+            kind: EntryKind::Forget,
+            ..ReviewLogEntry::manual(1, Utc::now(), learned(1), Review::default())
+        };
+        let mut entries = vec![first.clone(), other.clone(), forget.clone()];
+
+        assert_eq!(vec![other.clone(), forget.clone()], effective_entries(entries.clone()));
+
+        entries.push(ReviewLogEntry::undo(&forget, Utc::now()));
+        assert_eq!(vec![first, other], effective_entries(entries));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Utc};
+
+use crate::config;
+use crate::deck;
+use crate::history::{self, EntryKind};
+use crate::review::{DayBoundary, ReviewState};
+
+// New cards and reviews, either studied today or still allowed today
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailyCounts {
+    pub new: u32,
+    pub reviews: u32,
+}
+
+// Counts what was studied today in each deck of a tree, not including the
+// decks nested in it, so each review log is only read once. A new card counts
+// when it's first answered, a review when a learned card is answered.
+// Learning steps don't count towards either.
+pub fn get_studied_by_deck(
+    collection: &str,
+    deck: &str,
+    days: DayBoundary,
+    time: DateTime<Utc>,
+) -> BTreeMap<String, DailyCounts> {
+    let today = days.day(time);
+    let mut studied_by_deck = BTreeMap::new();
+    for deck in deck::get_deck_tree(collection, deck) {
+        let mut studied = DailyCounts::default();
+        for entries in history::read_deck_logs(collection, &deck).into_values() {
+            for entry in history::effective_entries(entries) {
+                if entry.kind != EntryKind::Review || days.day(entry.reviewed_at) != today {
+                    continue;
+                }
+                if entry.is_first_answer() {
+                    studied.new += 1;
+                } else if entry.before.state == ReviewState::Learned {
+                    studied.reviews += 1;
+                }
+            }
+        }
+        studied_by_deck.insert(deck, studied);
+    }
+    studied_by_deck
+}
+
+// Adds up what was studied in a deck and the decks nested inside it
+pub fn get_studied_in(studied_by_deck: &BTreeMap<String, DailyCounts>, deck: &str) -> DailyCounts {
+    let nested = format!("{}/", deck);
+    studied_by_deck
+        .iter()
+        .filter(|(id, _)| *id == deck || id.starts_with(&nested))
+        .fold(DailyCounts::default(), |total, (_, studied)| DailyCounts {
+            new: total.new + studied.new,
+            reviews: total.reviews + studied.reviews,
+        })
+}
+
+// The outermost deck a deck is nested in, or the deck itself. Counting what
+// was studied in its tree covers every limit that applies to the deck.
+pub fn get_limiting_tree(deck: &str) -> String {
+    deck::get_parent_decks(deck)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| deck.to_string())
+}
+
+// What is left of today's limits for a deck, given what was studied in the
+// tree of get_limiting_tree. A deck nested in others can't go past what is
+// left of theirs.
+pub fn get_remaining(
+    collection: &str,
+    deck: &str,
+    studied_by_deck: &BTreeMap<String, DailyCounts>,
+) -> Result<DailyCounts, String> {
+    let mut decks = deck::get_parent_decks(deck);
+    decks.push(deck.to_string());
+
+    let mut remaining = DailyCounts {
+        new: u32::MAX,
+        reviews: u32::MAX,
+    };
+    for deck in decks {
+        let config = config::load_deck_config(collection, &deck)?;
+        let studied = get_studied_in(studied_by_deck, &deck);
+        remaining = DailyCounts {
+            new: remaining.new.min(config.new_per_day.saturating_sub(studied.new)),
+            reviews: remaining.reviews.min(config.reviews_per_day.saturating_sub(studied.reviews)),
+        };
+    }
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::DECK_CONFIG_FILE;
+    use crate::limits::{get_remaining, get_studied_by_deck, get_studied_in, DailyCounts};
+    use crate::review::{DayBoundary, Review};
+    use crate::test_helpers::{answer, learned};
+    use chrono::{Duration, Utc};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn studied_today_counts_subdecks() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        fs::create_dir_all(Path::new(collection).join("parent").join("child")).unwrap();
+
+        answer(collection, "parent", "1", Review::default(), Review::default(), Utc::now());
+        answer(collection, "parent/child", "2", Review::default(), Review::default(), Utc::now());
+        answer(collection, "parent/child", "3", learned(0), Review::default(), Utc::now());
+        // A learning step and a review from two days ago don't count
+        let learning = Review { due: Some(Utc::now()), ..Review::default() };
+        answer(collection, "parent/child", "4", learning, Review::default(), Utc::now());
+        answer(collection, "parent", "5", learned(0), Review::default(), Utc::now() - Duration::days(2));
+
+        let studied = get_studied_by_deck(collection, "parent", DayBoundary::default(), Utc::now());
+        assert_eq!(DailyCounts { new: 1, reviews: 0 }, studied["parent"]);
+        assert_eq!(DailyCounts { new: 2, reviews: 1 }, get_studied_in(&studied, "parent"));
+        assert_eq!(DailyCounts { new: 1, reviews: 1 }, get_studied_in(&studied, "parent/child"));
+    }
+
+    #[test]
+    fn parent_limits_cap_children() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        let parent = Path::new(collection).join("parent");
+        fs::create_dir_all(parent.join("child")).unwrap();
+        fs::write(parent.join(DECK_CONFIG_FILE), "new_per_day: 3\n").unwrap();
+        fs::write(parent.join("child").join(DECK_CONFIG_FILE), "new_per_day: 10\nreviews_per_day: 5\n").unwrap();
+
+        answer(collection, "parent", "1", Review::default(), Review::default(), Utc::now());
+        let studied = get_studied_by_deck(collection, "parent", DayBoundary::default(), Utc::now());
+
+        assert_eq!(DailyCounts { new: 2, reviews: 200 }, get_remaining(collection, "parent", &studied).unwrap());
+        assert_eq!(DailyCounts { new: 2, reviews: 5 }, get_remaining(collection, "parent/child", &studied).unwrap());
+    }
+}
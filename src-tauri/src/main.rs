@@ -8,16 +8,27 @@ pub mod note;
 pub mod card;
 pub mod review;
 pub mod context;
+pub mod config;
+pub mod history;
+pub mod optimizer;
+pub mod limits;
+pub mod session;
+pub mod forecast;
+pub mod simulator;
+pub mod stats;
+#[cfg(test)]
+mod test_helpers;
 
 use std::env;
 
 fn main() {
     let collection_path = &env::var("COLLECTION_PATH").unwrap();
     tauri::Builder::default()
-        .manage(context::Context::from(collection_path))
+        .manage(context::Context::from(collection_path.as_str()))
+        .manage(session::SessionState::default())
         .invoke_handler(tauri::generate_handler![
             deck::get_decks_handler,
-            deck::create_deck,
+            deck::create_deck_handler,
             note::list_notes,
             note::create_note,
             note::preview_note,
@@ -25,7 +36,26 @@ fn main() {
             note::update_note,
             card::list_cards_to_review,
             card::render_card,
-            card::review_card,
+            card::list_leeches,
+            card::suspend_cards,
+            card::unsuspend_cards,
+            card::bury_cards,
+            card::forget_cards,
+            card::set_due_date,
+            card::reposition_cards,
+            card::preview_intervals,
+            optimizer::optimize_fsrs,
+            forecast::forecast,
+            simulator::simulate_reviews,
+            stats::get_daily_activity_stats,
+            stats::get_retention_stats,
+            stats::get_card_state_stats,
+            stats::get_review_heatmap,
+            stats::set_daily_goal,
+            session::start_session,
+            session::next_card,
+            session::review_card,
+            session::undo_review,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
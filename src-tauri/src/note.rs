@@ -11,8 +11,7 @@ use comrak::{markdown_to_html, ComrakOptions};
 use regex::Regex;
 use tauri::State;
 
-use crate::collection::CollectionPath;
-
+use crate::context::Context;
 use crate::deck;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -30,8 +29,9 @@ impl Note {
             template,
         }
     }
-    pub fn get_path(&self) -> PathBuf {
-        deck::get_deck_path(&self.deck_id).join(format!("{}_{}.md", self.note_id, self.template))
+    pub fn get_path(&self, collection: &str) -> PathBuf {
+        deck::get_deck_path(collection, &self.deck_id)
+            .join(format!("{}_{}.md", self.note_id, self.template))
     }
 }
 
@@ -144,14 +144,18 @@ fn render_back(fields: HashMap<String, String>, template: &str, card_num: u32) -
     )
 }
 
-#[tauri::command]
-pub fn read_note(note: Note) -> Result<HashMap<String, String>, String> {
-    match fs::read(note.get_path()) {
+pub fn get_note_fields(context: &Context, note: &Note) -> Result<HashMap<String, String>, String> {
+    match fs::read(note.get_path(&context.get_collection_path())) {
         Ok(f) => Ok(parse_note_into_fields(String::from_utf8(f).unwrap())),
         Err(err) => Err(err.to_string()),
     }
 }
 
+#[tauri::command]
+pub fn read_note(state: State<'_, Context>, note: Note) -> Result<HashMap<String, String>, String> {
+    get_note_fields(state.inner(), &note)
+}
+
 fn get_note_md(fields: HashMap<String, String>) -> String {
     let mut md = "".to_string();
     for (field, value) in fields.iter() {
@@ -161,7 +165,11 @@ fn get_note_md(fields: HashMap<String, String>) -> String {
 }
 
 #[tauri::command]
-pub fn create_note(mut note: Note, fields: HashMap<String, String>) -> Result<(), String> {
+pub fn create_note(
+    state: State<'_, Context>,
+    mut note: Note,
+    fields: HashMap<String, String>,
+) -> Result<(), String> {
     let time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
@@ -169,23 +177,119 @@ pub fn create_note(mut note: Note, fields: HashMap<String, String>) -> Result<()
         .to_string();
     note.note_id = format!("{}_{}", time, note.template);
 
-    match fs::write(note.get_path(), get_note_md(fields)) {
+    match fs::write(note.get_path(&state.inner().get_collection_path()), get_note_md(fields)) {
         Ok(..) => Ok(()),
         Err(err) => Err(err.to_string()),
     }
 }
 
 #[tauri::command]
-pub fn update_note(note: Note, fields: HashMap<String, String>) -> Result<(), String> {
-    match fs::write(note.get_path(), get_note_md(fields)) {
+pub fn update_note(
+    state: State<'_, Context>,
+    note: Note,
+    fields: HashMap<String, String>,
+) -> Result<(), String> {
+    match fs::write(note.get_path(&state.inner().get_collection_path()), get_note_md(fields)) {
         Ok(..) => Ok(()),
         Err(err) => Err(err.to_string()),
     }
 }
 
+// Tags are kept space separated in the note's Tags field
+pub fn get_tags(fields: &HashMap<String, String>) -> Vec<String> {
+    fields
+        .get("Tags")
+        .map(|tags| tags.split_whitespace().map(|tag| tag.to_string()).collect())
+        .unwrap_or_default()
+}
+
+// The line of the note's last Tags heading and the line its section ends
+// before, the next heading or the end of the note
+fn find_tags_section(lines: &[String]) -> Option<(usize, usize)> {
+    let heading = Regex::new("# (.*)").unwrap();
+    let index = lines
+        .iter()
+        .rposition(|line| heading.captures(line).is_some_and(|captures| &captures[1] == "Tags"))?;
+    let end = lines[index + 1..]
+        .iter()
+        .position(|line| heading.is_match(line))
+        .map_or(lines.len(), |offset| index + 1 + offset);
+    Some((index, end))
+}
+
+// Adds the tag to the note's Tags section, or a Tags section to the end of
+// the note if it has none. Everything else in the file is left as written.
+pub fn add_tag(context: &Context, note: &Note, tag: &str) -> Result<(), String> {
+    let path = note.get_path(&context.get_collection_path());
+    let md = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+    if get_tags(&parse_note_into_fields(md.clone())).iter().any(|existing| existing == tag) {
+        return Ok(());
+    }
+
+    let mut lines: Vec<String> = md.split('\n').map(|line| line.to_string()).collect();
+    let tagged = match find_tags_section(&lines) {
+        Some((index, end)) => {
+            match (index + 1..end).rev().find(|i| !lines[*i].trim().is_empty()) {
+                Some(last) => {
+                    let tags = lines[last].trim_end().to_string();
+                    lines[last] = format!("{} {}{}", tags, tag, &lines[last][tags.len()..]);
+                }
+                None => lines.insert(index + 1, tag.to_string()),
+            }
+            lines.join("\n")
+        }
+        None if md.is_empty() || md.ends_with('\n') => format!("{}# Tags\n{}\n", md, tag),
+        None => format!("{}\n# Tags\n{}\n", md, tag),
+    };
+
+    fs::write(path, tagged).map_err(|err| err.to_string())
+}
+
+// Takes the tag out of the note's Tags section, along with the space before
+// it. A line left without tags is dropped, everything else is left as written.
+pub fn remove_tag(context: &Context, note: &Note, tag: &str) -> Result<(), String> {
+    let path = note.get_path(&context.get_collection_path());
+    let md = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+    let mut lines: Vec<String> = md.split('\n').map(|line| line.to_string()).collect();
+    let (index, end) = match find_tags_section(&lines) {
+        Some(section) => section,
+        None => return Ok(()),
+    };
+
+    let word = Regex::new(r"\S+").unwrap();
+    let mut changed = false;
+    for i in (index + 1..end).rev() {
+        let spans: Vec<(usize, usize)> = word
+            .find_iter(&lines[i])
+            .filter(|found| found.as_str() == tag)
+            .map(|found| (found.start(), found.end()))
+            .collect();
+        for (start, end) in spans.into_iter().rev() {
+            let line = &mut lines[i];
+            let start = line[..start].trim_end().len();
+            // The first tag takes the space after it instead
+            let end = match start {
+                0 => line.len() - line[end..].trim_start().len(),
+                _ => end,
+            };
+            line.replace_range(start..end, "");
+            changed = true;
+            if line.trim().is_empty() {
+                lines.remove(i);
+                break;
+            }
+        }
+    }
+
+    match changed {
+        true => fs::write(path, lines.join("\n")).map_err(|err| err.to_string()),
+        false => Ok(()),
+    }
+}
+
 #[tauri::command]
-pub fn list_notes(state: State<CollectionPath>, deck: &str) -> Result<Vec<Note>, String> {
-    match fs::read_dir(deck::get_deck_path(deck, &state.0)) {
+pub fn list_notes(state: State<'_, Context>, deck: &str) -> Result<Vec<Note>, String> {
+    match fs::read_dir(deck::get_deck_path(&state.inner().get_collection_path(), deck)) {
         Ok(paths) => Ok(get_notes_from_paths(deck, paths)),
         Err(err) => Err(err.to_string()),
     }
@@ -205,9 +309,8 @@ pub fn preview_note(
     }
 }
 
-#[tauri::command]
-pub fn render_note_card(note: Note, card_num: u32, back: bool) -> Result<String, String> {
-    let fields = read_note(note.clone())?;
+pub fn render_note_card(context: &Context, note: Note, card_num: u32, back: bool) -> Result<String, String> {
+    let fields = get_note_fields(context, &note)?;
     if back {
         Ok(render_back(fields, &note.template, card_num))
     } else {
@@ -217,7 +320,8 @@ pub fn render_note_card(note: Note, card_num: u32, back: bool) -> Result<String,
 
 #[cfg(test)]
 mod tests {
-    use crate::note::{preview_note, read_note, Note};
+    use crate::context::Context;
+    use crate::note::{add_tag, get_note_fields, get_tags, preview_note, remove_tag, Note};
     use std::ops::Deref;
     use std::path::{Path, PathBuf};
     use std::{collections::HashMap, fs};
@@ -257,15 +361,18 @@ mod tests {
 
         let tempdir = tempfile::tempdir().unwrap();
         let mut path = PathBuf::from(&tempdir.path());
-        env::set_var("COLLECTION_PATH", path.to_str().unwrap());
+        let context = Context::from(path.to_str().unwrap());
         path.push("basicdeck");
         copy_recursively(source, path).unwrap();
 
-        let fields = read_note(Note {
-            deck_id: "basicdeck".into(),
-            note_id: "123".into(),
-            template: "basic".into(),
-        })
+        let fields = get_note_fields(
+            &context,
+            &Note {
+                deck_id: "basicdeck".into(),
+                note_id: "123".into(),
+                template: "basic".into(),
+            },
+        )
         .unwrap();
 
         assert_eq!("Question", fields.get("Front").unwrap());
@@ -275,6 +382,83 @@ mod tests {
         tempdir.close().unwrap();
     }
 
+    #[test]
+    fn add_tag_basic() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let context = Context::from(tempdir.path().to_str().unwrap());
+        fs::create_dir(tempdir.path().join("basicdeck")).unwrap();
+        fs::write(tempdir.path().join("basicdeck").join("123_basic.md"), "# Front\nQuestion\n# Back\nAnswer\n").unwrap();
+        let note = Note {
+            deck_id: "basicdeck".into(),
+            note_id: "123".into(),
+            template: "basic".into(),
+        };
+
+        add_tag(&context, &note, "leech").unwrap();
+        add_tag(&context, &note, "leech").unwrap();
+
+        let fields = get_note_fields(&context, &note).unwrap();
+        assert_eq!(vec!["leech".to_string()], get_tags(&fields));
+        assert_eq!("Question", fields.get("Front").unwrap());
+        assert_eq!("Answer", fields.get("Back").unwrap());
+        assert_eq!(
+            "# Front\nQuestion\n# Back\nAnswer\n# Tags\nleech\n",
+            fs::read_to_string(tempdir.path().join("basicdeck").join("123_basic.md")).unwrap()
+        );
+    }
+
+    #[test]
+    fn add_tag_keeps_the_rest_of_the_note() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let context = Context::from(tempdir.path().to_str().unwrap());
+        let path = tempdir.path().join("basicdeck").join("123_basic.md");
+        fs::create_dir(tempdir.path().join("basicdeck")).unwrap();
+        let note = Note {
+            deck_id: "basicdeck".into(),
+            note_id: "123".into(),
+            template: "basic".into(),
+        };
+
+        fs::write(&path, "Written before any field\n\n# Front\nQuestion\n# Tags\nhard  \n\n# Back\nAnswer").unwrap();
+        add_tag(&context, &note, "leech").unwrap();
+        assert_eq!(
+            "Written before any field\n\n# Front\nQuestion\n# Tags\nhard leech  \n\n# Back\nAnswer",
+            fs::read_to_string(&path).unwrap()
+        );
+        remove_tag(&context, &note, "leech").unwrap();
+        assert_eq!(
+            "Written before any field\n\n# Front\nQuestion\n# Tags\nhard  \n\n# Back\nAnswer",
+            fs::read_to_string(&path).unwrap()
+        );
+
+        fs::write(&path, "# Back\nAnswer\n# Tags\n\n# Front\nQuestion").unwrap();
+        add_tag(&context, &note, "leech").unwrap();
+        assert_eq!("# Back\nAnswer\n# Tags\nleech\n\n# Front\nQuestion", fs::read_to_string(&path).unwrap());
+        remove_tag(&context, &note, "leech").unwrap();
+        assert_eq!("# Back\nAnswer\n# Tags\n\n# Front\nQuestion", fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn remove_tag_leaves_other_tags() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let context = Context::from(tempdir.path().to_str().unwrap());
+        let path = tempdir.path().join("basicdeck").join("123_basic.md");
+        fs::create_dir(tempdir.path().join("basicdeck")).unwrap();
+        let note = Note {
+            deck_id: "basicdeck".into(),
+            note_id: "123".into(),
+            template: "basic".into(),
+        };
+
+        fs::write(&path, "# Front\nQuestion\n# Tags\nleech hard\nleeches\n  leech  \nverbs leech").unwrap();
+        remove_tag(&context, &note, "leech").unwrap();
+        assert_eq!("# Front\nQuestion\n# Tags\nhard\nleeches\nverbs", fs::read_to_string(&path).unwrap());
+
+        fs::write(&path, "# Front\nleech\n# Back\nAnswer").unwrap();
+        remove_tag(&context, &note, "leech").unwrap();
+        assert_eq!("# Front\nleech\n# Back\nAnswer", fs::read_to_string(&path).unwrap());
+    }
+
     #[test]
     fn preview_note_basic() {
         let mut fields = HashMap::<String, String>::new();
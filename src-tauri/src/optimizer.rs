@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Utc};
+use tauri::State;
+
+use crate::config;
+use crate::context::Context;
+use crate::deck;
+use crate::history::{self, ReviewLogEntry};
+use crate::review::{Fsrs, ReviewScore};
+
+const EPOCHS: usize = 200;
+const LEARNING_RATE: f64 = 0.04;
+const GRADIENT_STEP: f64 = 1e-4;
+const MINIMUM_REVIEWS: usize = 100;
+
+// Keeps every weight in the range where the FSRS curves stay well behaved
+const WEIGHT_BOUNDS: [(f64, f64); 17] = [
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (1.0, 10.0),
+    (0.1, 5.0),
+    (0.1, 5.0),
+    (0.0, 0.5),
+    (0.0, 3.0),
+    (0.1, 0.8),
+    (0.01, 2.5),
+    (0.5, 5.0),
+    (0.01, 0.2),
+    (0.01, 0.9),
+    (0.01, 2.0),
+    (0.0, 1.0),
+    (1.0, 6.0),
+];
+
+// The reviews of a single card, oldest first
+pub type CardHistory = Vec<(DateTime<Utc>, ReviewScore)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Loss {
+    pub log_loss: f64,
+    pub rmse: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Optimization {
+    pub reviews: usize,
+    pub before: Loss,
+    pub after: Loss,
+    pub weights: Vec<f64>,
+}
+
+// Legacy entries carry no timestamps, so they can't be used for training.
+// Manual entries aren't reviews and are left out as well, as are reviews that
+// were undone.
+pub fn card_histories(logs: impl IntoIterator<Item = Vec<ReviewLogEntry>>) -> Vec<CardHistory> {
+    logs.into_iter()
+        .flat_map(|entries| {
+            let mut cards: BTreeMap<u32, CardHistory> = BTreeMap::new();
+            for entry in history::effective_entries(entries)
+                .into_iter()
+                .filter(|entry| entry.version > 0)
+            {
+                if let Some(score) = entry.score {
+                    cards
+                        .entry(entry.card_num)
+                        .or_default()
+                        .push((entry.reviewed_at, score));
+                }
+            }
+            cards.into_values()
+        })
+        .map(|mut reviews| {
+            reviews.sort_by_key(|(reviewed_at, _)| *reviewed_at);
+            reviews
+        })
+        .collect()
+}
+
+// Replays each history through the memory model and pairs the predicted
+// recall probability of every review with whether it was actually recalled.
+// Reviews on the same day as the previous one don't change memory state.
+fn predictions(fsrs: &Fsrs, histories: &[CardHistory]) -> Vec<(f64, f64)> {
+    let mut predictions = vec![];
+    for history in histories {
+        let ((first_time, first_score), rest) = match history.split_first() {
+            Some(split) => split,
+            None => continue,
+        };
+        let mut stability = fsrs.initial_stability(first_score);
+        let mut difficulty = fsrs.initial_difficulty(first_score);
+        let mut last_time = *first_time;
+
+        for (time, score) in rest {
+            let elapsed = (*time - last_time).num_seconds() as f64 / 86400.0;
+            last_time = *time;
+            if elapsed < 1.0 {
+                continue;
+            }
+
+            let retrievability = Fsrs::retrievability(elapsed, stability);
+            let recalled = match score {
+                ReviewScore::Again => 0.0,
+                _ => 1.0,
+            };
+            predictions.push((retrievability, recalled));
+
+            stability = match score {
+                ReviewScore::Again => fsrs.forget_stability(difficulty, stability, retrievability),
+                _ => fsrs.recall_stability(difficulty, stability, retrievability, score),
+            };
+            difficulty = fsrs.next_difficulty(difficulty, score);
+        }
+    }
+    predictions
+}
+
+pub fn evaluate(fsrs: &Fsrs, histories: &[CardHistory]) -> Loss {
+    let predictions = predictions(fsrs, histories);
+    if predictions.is_empty() {
+        return Loss { log_loss: 0.0, rmse: 0.0 };
+    }
+
+    let count = predictions.len() as f64;
+    let log_loss = predictions
+        .iter()
+        .map(|(p, y)| {
+            let p = p.clamp(1e-6, 1.0 - 1e-6);
+            -(y * p.ln() + (1.0 - y) * (1.0 - p).ln())
+        })
+        .sum::<f64>()
+        / count;
+    let rmse = (predictions.iter().map(|(p, y)| (p - y).powi(2)).sum::<f64>() / count).sqrt();
+
+    Loss { log_loss, rmse }
+}
+
+fn clamp_weights(weights: &mut [f64]) -> Result<(), String> {
+    if weights.len() != WEIGHT_BOUNDS.len() {
+        return Err(format!("Expected {} FSRS weights, found {}", WEIGHT_BOUNDS.len(), weights.len()));
+    }
+    for (weight, (low, high)) in weights.iter_mut().zip(WEIGHT_BOUNDS.iter()) {
+        *weight = weight.clamp(*low, *high);
+    }
+    Ok(())
+}
+
+// Fits the weights with Adam, using central differences for the gradient of
+// the log-loss. Returns the best weights seen.
+pub fn optimize(initial: &Fsrs, histories: &[CardHistory]) -> Result<Fsrs, String> {
+    let mut current = initial.clone();
+    clamp_weights(&mut current.weights)?;
+
+    let mut best = current.clone();
+    let mut best_loss = evaluate(&best, histories).log_loss;
+
+    let (beta1, beta2, epsilon) = (0.9, 0.999, 1e-8);
+    let mut m = vec![0.0; current.weights.len()];
+    let mut v = vec![0.0; current.weights.len()];
+
+    for epoch in 1..=EPOCHS {
+        let gradient: Vec<f64> = (0..current.weights.len())
+            .map(|i| {
+                let mut up = current.clone();
+                up.weights[i] += GRADIENT_STEP;
+                let mut down = current.clone();
+                down.weights[i] -= GRADIENT_STEP;
+                (evaluate(&up, histories).log_loss - evaluate(&down, histories).log_loss)
+                    / (2.0 * GRADIENT_STEP)
+            })
+            .collect();
+
+        for i in 0..current.weights.len() {
+            m[i] = beta1 * m[i] + (1.0 - beta1) * gradient[i];
+            v[i] = beta2 * v[i] + (1.0 - beta2) * gradient[i].powi(2);
+            let m_hat = m[i] / (1.0 - beta1.powi(epoch as i32));
+            let v_hat = v[i] / (1.0 - beta2.powi(epoch as i32));
+            current.weights[i] -= LEARNING_RATE * m_hat / (v_hat.sqrt() + epsilon);
+        }
+        clamp_weights(&mut current.weights)?;
+
+        let loss = evaluate(&current, histories).log_loss;
+        if loss < best_loss {
+            best_loss = loss;
+            best = current.clone();
+        }
+    }
+
+    Ok(best)
+}
+
+// Fits FSRS weights to the review history of a deck and the decks nested in
+// it, or of the whole collection when no deck is given, and saves them to that deck's or the
+// collection's config.
+pub fn optimize_weights(context: &Context, deck: Option<&str>) -> Result<Optimization, String> {
+    let collection = context.get_collection_path();
+    let fsrs = match deck {
+        Some(deck) => config::load_deck_config(&collection, deck)?.scheduler.fsrs,
+        None => config::load_collection_config(&collection)?.scheduler.fsrs,
+    };
+
+    let histories = card_histories(
+        deck::get_deck_scope(context, deck)?
+            .iter()
+            .flat_map(|deck| history::read_deck_logs(&collection, deck).into_values()),
+    );
+
+    let reviews = predictions(&fsrs, &histories).len();
+    if reviews < MINIMUM_REVIEWS {
+        return Err(format!(
+            "At least {} reviews are needed to optimize, found {}",
+            MINIMUM_REVIEWS, reviews
+        ));
+    }
+
+    let optimized = optimize(&fsrs, &histories)?;
+    config::save_fsrs_weights(&collection, deck, &optimized.weights)?;
+
+    Ok(Optimization {
+        reviews,
+        before: evaluate(&fsrs, &histories),
+        after: evaluate(&optimized, &histories),
+        weights: optimized.weights,
+    })
+}
+
+// Fitting takes a while, so it runs off the thread that handles commands
+#[tauri::command]
+pub async fn optimize_fsrs(state: State<'_, Context>, deck: Option<String>) -> Result<Optimization, String> {
+    let context = Context::from(state.inner().get_collection_path().as_str());
+    tauri::async_runtime::spawn_blocking(move || optimize_weights(&context, deck.as_deref()))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+    use crate::context::Context;
+    use crate::history::{append_entry, get_reviews_path, ReviewLogEntry};
+    use crate::optimizer::{evaluate, optimize, optimize_weights, CardHistory, WEIGHT_BOUNDS};
+    use crate::review::{Fsrs, Review, ReviewScore};
+    use chrono::{Duration, TimeZone, Utc};
+
+    // Every card is recalled at short intervals and forgotten once the gap
+    // reaches a month, which the default weights underestimate.
+    fn histories() -> Vec<CardHistory> {
+        let start = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+        (0..60)
+            .map(|card| {
+                let mut time = start + Duration::hours(card);
+                let mut history = vec![(time, ReviewScore::Good)];
+                for gap in [1, 3, 8, 20, 30] {
+                    time += Duration::days(gap);
+                    let score = if gap >= 30 && card % 2 == 0 {
+                        ReviewScore::Again
+                    } else {
+                        ReviewScore::Good
+                    };
+                    history.push((time, score));
+                }
+                history
+            })
+            .collect()
+    }
+
+    #[test]
+    fn optimize_reduces_loss() {
+        let histories = histories();
+        let before = evaluate(&Fsrs::default(), &histories);
+
+        let optimized = optimize(&Fsrs::default(), &histories).unwrap();
+        let after = evaluate(&optimized, &histories);
+
+        assert!(after.log_loss < before.log_loss, "{:?} should improve on {:?}", after, before);
+        assert!(optimized
+            .weights
+            .iter()
+            .zip(WEIGHT_BOUNDS.iter())
+            .all(|(weight, (low, high))| low <= weight && weight <= high));
+    }
+
+    #[test]
+    fn optimize_needs_every_weight() {
+        let fsrs = Fsrs {
+            weights: vec![1.0; 4],
+            ..Fsrs::default()
+        };
+
+        assert!(optimize(&fsrs, &histories()).is_err());
+    }
+
+    #[test]
+    fn optimize_weights_saves_deck_config() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        std::fs::create_dir_all(tempdir.path().join("testdeck").join("nested")).unwrap();
+
+        // Half the history is in the nested deck, and still counts
+        for (note, history) in histories().into_iter().enumerate() {
+            let deck = if note % 2 == 0 { "testdeck" } else { "testdeck/nested" };
+            let path = get_reviews_path(collection, deck).join(format!("{}.jsonl", note));
+            for (time, score) in history {
+                let entry = ReviewLogEntry::new(1, time, score, Review::default(), Review::default());
+                append_entry(&path, &entry).unwrap();
+            }
+        }
+
+        let optimization = optimize_weights(&Context::from(collection), Some("testdeck")).unwrap();
+
+        assert_eq!(300, optimization.reviews);
+        assert!(optimization.after.log_loss < optimization.before.log_loss);
+        assert_eq!(
+            optimization.weights,
+            config::load_deck_config(collection, "testdeck").unwrap().scheduler.fsrs.weights
+        );
+        assert_eq!(Fsrs::default(), config::load_collection_config(collection).unwrap().scheduler.fsrs);
+    }
+
+    #[test]
+    fn optimize_weights_needs_history() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        std::fs::create_dir(tempdir.path().join("testdeck")).unwrap();
+
+        assert!(optimize_weights(&Context::from(collection), Some("testdeck")).is_err());
+    }
+}
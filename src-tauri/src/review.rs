@@ -1,6 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 
 // TODO: Reconsider the interfaces that are used to
 // render notes -- should probably just have a function
@@ -21,13 +23,26 @@ pub enum ReviewScore {
     Easy,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Review {
     pub due: Option<DateTime<Utc>>,
     pub interval: f64,
     pub ease: f64,
     pub state: ReviewState,
     pub steps: u32,
+    // FSRS memory state, unset until the card is scheduled by FSRS
+    pub stability: Option<f64>,
+    pub difficulty: Option<f64>,
+    // Times the card was forgotten after being learned
+    #[serde(default)]
+    pub lapses: u32,
+    #[serde(default)]
+    pub suspended: bool,
+    #[serde(default)]
+    pub buried_until: Option<DateTime<Utc>>,
+    // Where a new card sits in the new card queue
+    #[serde(default)]
+    pub position: Option<u32>,
 }
 
 impl Review {
@@ -44,10 +59,50 @@ impl Review {
             ease,
             state,
             steps,
+            stability: None,
+            difficulty: None,
+            lapses: 0,
+            suspended: false,
+            buried_until: None,
+            position: None,
         }
     }
 }
 
+impl Review {
+    // Suspended and buried cards are kept out of the review queue
+    pub fn is_hidden(&self, time: DateTime<Utc>) -> bool {
+        self.suspended || self.buried_until.is_some_and(|until| time < until)
+    }
+
+    // Days since the card was last reviewed, going by its interval
+    pub fn elapsed_days(&self, time: DateTime<Utc>) -> f64 {
+        match self.due {
+            Some(due) => self.interval - (due - time).num_seconds() as f64 / 86400.0,
+            None => 0.0,
+        }
+    }
+
+    // Estimated chance of recalling the card now. Cards scheduled without
+    // FSRS use their interval in place of stability.
+    pub fn retrievability(&self, time: DateTime<Utc>) -> f64 {
+        let stability = self.stability.unwrap_or(self.interval).max(0.1);
+        Fsrs::retrievability(self.elapsed_days(time), stability)
+    }
+
+    // How far past due the card is, relative to its interval
+    pub fn overdueness(&self, time: DateTime<Utc>) -> f64 {
+        (self.elapsed_days(time) - self.interval) / self.interval.max(1.0)
+    }
+}
+
+impl Default for Review {
+    fn default() -> Self {
+        Review::new(None, 1.0, 2.5, ReviewState::New, 0)
+    }
+}
+
+// Defaults for the settings a deck.yaml can override
 const EASY_INTERVAL: u32 = 4;
 const EASY_BONUS: f64 = 1.3;
 const GRADUATION_INTERVAL: u32 = 1;
@@ -57,107 +112,587 @@ const RELEARNING_STEPS: [u32; 1] = [10];
 
 const MINIMUM_INTERVAL: f64 = 1.0;
 const MINIMUM_EASE: f64 = 1.3;
+const HARD_MULTIPLIER: f64 = 1.2;
+const MAXIMUM_INTERVAL: f64 = 36500.0;
+const INTERVAL_MODIFIER: f64 = 1.0;
 
-pub fn score_card(mut review: Review, time: DateTime<Utc>, score: ReviewScore) -> Review {
-    match review.state {
-        ReviewState::New => match score {
-            ReviewScore::Easy => {
-                review.state = ReviewState::Learned;
-                review.interval = EASY_INTERVAL.into();
-                if let Some(due) = time.checked_add_signed(Duration::days(GRADUATION_INTERVAL.into())) {
-                    review.due = Some(due);
-                }
-                review.steps = 0;
-                review
-            }
-            ReviewScore::Again => {
-                review.steps = 0;
-                if let Some(due) = time.checked_add_signed(Duration::minutes(NEW_STEPS[0].into())) {
-                    review.due = Some(due);
-                }
-                review
-            }
-            ReviewScore::Hard => {
-                if let Some(due) = time.checked_add_signed(Duration::minutes(1)) {
-                    review.due = Some(due);
-                }
-                review
-            }
-            ReviewScore::Good => {
-                review.steps += 1;
-                if review.steps >= NEW_STEPS.len().try_into().unwrap() {
+// A scheduler takes the memory state of a card, the score it was given and
+// the time of the review, and returns the next memory state.
+pub trait Scheduler {
+    fn schedule(&self, review: Review, time: DateTime<Utc>, score: ReviewScore) -> Review;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    #[default]
+    Sm2,
+    Fsrs,
+}
+
+// Anki's variant of SM-2. The learning steps are in minutes, the intervals
+// in days. FSRS uses the same learning steps.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sm2 {
+    pub learning_steps: Vec<u32>,
+    pub relearning_steps: Vec<u32>,
+    pub graduating_interval: u32,
+    pub easy_interval: u32,
+    pub easy_bonus: f64,
+    pub minimum_ease: f64,
+    pub hard_multiplier: f64,
+    // Scales every review interval, before the maximum is applied
+    pub interval_modifier: f64,
+    pub maximum_interval: f64,
+}
+
+impl Default for Sm2 {
+    fn default() -> Self {
+        Sm2 {
+            learning_steps: NEW_STEPS.to_vec(),
+            relearning_steps: RELEARNING_STEPS.to_vec(),
+            graduating_interval: GRADUATION_INTERVAL,
+            easy_interval: EASY_INTERVAL,
+            easy_bonus: EASY_BONUS,
+            minimum_ease: MINIMUM_EASE,
+            hard_multiplier: HARD_MULTIPLIER,
+            interval_modifier: INTERVAL_MODIFIER,
+            maximum_interval: MAXIMUM_INTERVAL,
+        }
+    }
+}
+
+impl Sm2 {
+    fn graduate(&self, mut review: Review, time: DateTime<Utc>) -> Review {
+        review.state = ReviewState::Learned;
+        review.steps = 0;
+        review.interval = self.graduating_interval.into();
+        if let Some(due) = time.checked_add_signed(Duration::days(self.graduating_interval.into())) {
+            review.due = Some(due);
+        }
+        review
+    }
+
+    fn relearned(&self, mut review: Review, time: DateTime<Utc>) -> Review {
+        review.state = ReviewState::Learned;
+        review.steps = 0;
+        if let Some(due) = days_due(time, review.interval) {
+            review.due = Some(due);
+        }
+        review
+    }
+}
+
+impl Scheduler for Sm2 {
+    fn schedule(&self, mut review: Review, time: DateTime<Utc>, score: ReviewScore) -> Review {
+        match review.state {
+            ReviewState::New => match score {
+                ReviewScore::Easy => {
                     review.state = ReviewState::Learned;
+                    review.interval = self.easy_interval.into();
+                    if let Some(due) = days_due(time, review.interval) {
+                        review.due = Some(due);
+                    }
                     review.steps = 0;
-                    if let Some(due) =
-                        time.checked_add_signed(Duration::days(GRADUATION_INTERVAL.into()))
-                    {
+                    review
+                }
+                _ => match next_step(&self.learning_steps, review.steps, &score) {
+                    Some(step) => {
+                        review.steps = step;
+                        if let Some(due) = step_due(time, &self.learning_steps, step) {
+                            review.due = Some(due);
+                        }
+                        review
+                    }
+                    None => self.graduate(review, time),
+                },
+            },
+            ReviewState::Learned => match score {
+                ReviewScore::Good => {
+                    review.interval = (review.interval * review.ease * self.interval_modifier)
+                        .min(self.maximum_interval);
+                    if let Some(due) = days_due(time, review.interval) {
                         review.due = Some(due);
                     }
-                } else {
-                    if let Some(due) = time.checked_add_signed(Duration::minutes(NEW_STEPS[1].into())) {
+                    review
+                }
+                ReviewScore::Easy => {
+                    review.interval = (review.interval
+                        * review.ease
+                        * self.easy_bonus
+                        * self.interval_modifier)
+                        .min(self.maximum_interval);
+                    review.ease += 0.15;
+                    if let Some(due) = days_due(time, review.interval) {
                         review.due = Some(due);
                     }
+                    review
                 }
-                review
-            }
-        },
-        ReviewState::Learned => match score {
-            ReviewScore::Good => {
-                review.interval *= review.ease;
-                if let Some(due) = time.checked_add_signed(Duration::seconds(
-                    (review.interval * 24.0 * 60.0 * 60.0).round() as i64,
-                )) {
-                    review.due = Some(due);
+                ReviewScore::Hard => {
+                    review.interval = (review.interval * self.hard_multiplier * self.interval_modifier)
+                        .min(self.maximum_interval);
+                    review.ease = (review.ease - 0.15).max(self.minimum_ease);
+                    if let Some(due) = days_due(time, review.interval) {
+                        review.due = Some(due);
+                    }
+                    review
                 }
-                review
-            }
-            ReviewScore::Easy => {
-                review.interval *= review.ease * EASY_BONUS;
-                review.ease += 0.15;
-                if let Some(due) = time.checked_add_signed(Duration::seconds(
-                    (review.interval * 24.0 * 60.0 * 60.0).round() as i64,
-                )) {
-                    review.due = Some(due);
+                ReviewScore::Again => {
+                    review.state = ReviewState::Relearning;
+                    review.steps = 0;
+                    review.lapses += 1;
+                    review.interval = (review.interval * 0.7)
+                        .max(MINIMUM_INTERVAL)
+                        .min(self.maximum_interval);
+                    review.ease = (review.ease - 0.2).max(self.minimum_ease);
+                    match step_due(time, &self.relearning_steps, 0) {
+                        Some(due) => {
+                            review.due = Some(due);
+                            review
+                        }
+                        None => self.relearned(review, time),
+                    }
                 }
-                review
-            }
-            ReviewScore::Hard => {
-                review.interval *= 1.2;
-                review.ease = (review.ease - 0.15).max(MINIMUM_EASE);
-                if let Some(due) = time.checked_add_signed(Duration::seconds(
-                    (review.interval * 24.0 * 60.0 * 60.0).round() as i64,
-                )) {
+            },
+            ReviewState::Relearning => match score {
+                ReviewScore::Easy => self.relearned(review, time),
+                _ => match next_step(&self.relearning_steps, review.steps, &score) {
+                    Some(step) => {
+                        review.steps = step;
+                        if let Some(due) = step_due(time, &self.relearning_steps, step) {
+                            review.due = Some(due);
+                        }
+                        review
+                    }
+                    None => self.relearned(review, time),
+                },
+            },
+        }
+    }
+}
+
+pub fn score_card(review: Review, time: DateTime<Utc>, score: ReviewScore) -> Review {
+    Sm2::default().schedule(review, time, score)
+}
+
+// How long until the card is due in short form, e.g. "10m", "3d" or "1.5y".
+// Goes by the due time, which can differ from the interval, e.g. when Easy
+// graduates a new card. Cards without one go by their interval.
+pub fn format_interval(review: &Review, time: DateTime<Utc>) -> String {
+    let days = match review.due {
+        Some(due) => (due - time).num_seconds() as f64 / 86400.0,
+        None => review.interval,
+    };
+    let minutes = days * 24.0 * 60.0;
+    if minutes < 60.0 {
+        format!("{}m", minutes.round().max(1.0))
+    } else if minutes < 24.0 * 60.0 {
+        format!("{}h", (minutes / 60.0).round())
+    } else if days < 30.0 {
+        format!("{}d", days.round())
+    } else if days < 365.0 {
+        format!("{:.1}mo", days / 30.0)
+    } else {
+        format!("{:.1}y", days / 365.0)
+    }
+}
+
+// Walks a card through its learning or relearning steps. Again goes back to
+// the first step, Hard repeats the current one and Good moves to the next.
+// Returns None once the card should leave the steps.
+fn next_step(steps: &[u32], step: u32, score: &ReviewScore) -> Option<u32> {
+    let last = (steps.len() as u32).checked_sub(1)?;
+    match score {
+        ReviewScore::Again => Some(0),
+        ReviewScore::Hard => Some(step.min(last)),
+        ReviewScore::Good if step < last => Some(step + 1),
+        ReviewScore::Good | ReviewScore::Easy => None,
+    }
+}
+
+// Same-day learning steps shared by the schedulers, in minutes
+fn step_due(time: DateTime<Utc>, steps: &[u32], step: u32) -> Option<DateTime<Utc>> {
+    steps
+        .get(step as usize)
+        .and_then(|minutes| time.checked_add_signed(Duration::minutes((*minutes).into())))
+}
+
+fn days_due(time: DateTime<Utc>, days: f64) -> Option<DateTime<Utc>> {
+    time.checked_add_signed(Duration::seconds((days * 24.0 * 60.0 * 60.0).round() as i64))
+}
+
+// Review intervals from this length up are fuzzed, by a share of the
+// interval that shrinks as the interval grows
+const FUZZ_RANGES: [(f64, f64, f64); 3] = [
+    (2.5, 7.0, 0.15),
+    (7.0, 20.0, 0.1),
+    (20.0, f64::MAX, 0.05),
+];
+
+// The number of days a review interval may move in either direction
+pub fn fuzz_delta(interval: f64) -> f64 {
+    if interval < FUZZ_RANGES[0].0 {
+        return 0.0;
+    }
+    FUZZ_RANGES
+        .iter()
+        .map(|(start, end, factor)| factor * (interval.min(*end) - start).max(0.0))
+        .sum::<f64>()
+        + 1.0
+}
+
+// FNV-1a over the note id, card number and number of earlier reviews, so
+// replaying a log fuzzes every review the same way.
+pub fn fuzz_seed(note_id: &str, card_num: u32, reviews: usize) -> u64 {
+    note_id
+        .bytes()
+        .chain(card_num.to_le_bytes())
+        .chain((reviews as u64).to_le_bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+// Maps a seed to [0, 1) with a splitmix64 round
+fn seed_fraction(seed: u64) -> f64 {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Moves a learned card's due date to a random point within the fuzz range of
+// its interval, so cards reviewed together don't stay together.
+pub fn fuzz(mut review: Review, seed: u64) -> Review {
+    let delta = fuzz_delta(review.interval);
+    if review.state != ReviewState::Learned || delta == 0.0 {
+        return review;
+    }
+
+    let fuzzed = (review.interval - delta + seed_fraction(seed) * 2.0 * delta).max(1.0);
+    if let Some(due) = review.due {
+        review.due = days_due(due, fuzzed - review.interval);
+    }
+    review.interval = fuzzed;
+    review
+}
+
+// Pulls the due date in when fuzz has pushed the interval past the maximum
+pub fn limit_interval(mut review: Review, maximum_interval: f64) -> Review {
+    if review.interval > maximum_interval {
+        if let Some(due) = review.due {
+            review.due = days_due(due, maximum_interval - review.interval);
+        }
+        review.interval = maximum_interval;
+    }
+    review
+}
+
+// Number of cards due on each day
+pub type DueCounts = BTreeMap<NaiveDate, usize>;
+
+// Where one day ends and the next begins, as the offset from midnight UTC.
+// The default starts days at midnight UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DayBoundary {
+    offset: Duration,
+}
+
+impl DayBoundary {
+    pub fn new(utc_offset: Duration, rollover_hour: u32) -> Self {
+        DayBoundary {
+            offset: utc_offset - Duration::hours(rollover_hour.into()),
+        }
+    }
+
+    // The day a time falls on
+    pub fn day(&self, time: DateTime<Utc>) -> NaiveDate {
+        (time + self.offset).date_naive()
+    }
+
+    pub fn start(&self, day: NaiveDate) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap()) - self.offset
+    }
+
+    pub fn next_day(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        self.start(self.day(time) + Duration::days(1))
+    }
+
+    // Learned cards are due from the start of their day, so intervals count
+    // days rather than exact 24 hour periods. Learning steps stay precise.
+    pub fn round_due(&self, mut review: Review) -> Review {
+        if review.state == ReviewState::Learned {
+            review.due = review.due.map(|due| self.start(self.day(due)));
+        }
+        review
+    }
+
+    pub fn is_due(&self, review: &Review, time: DateTime<Utc>) -> bool {
+        match (&review.state, review.due) {
+            (_, None) => true,
+            (ReviewState::Learned, Some(due)) => self.day(due) <= self.day(time),
+            (_, Some(due)) => due < time,
+        }
+    }
+}
+
+impl Default for DayBoundary {
+    fn default() -> Self {
+        DayBoundary::new(Duration::zero(), 0)
+    }
+}
+
+// Like fuzz, but moves the due date by whole days to the day within the fuzz
+// range with the fewest cards already due. Ties are broken by the seed.
+pub fn load_balance(mut review: Review, seed: u64, due_counts: &DueCounts, days: DayBoundary) -> Review {
+    let delta = fuzz_delta(review.interval);
+    let due = match review.due {
+        Some(due) if review.state == ReviewState::Learned && delta > 0.0 => due,
+        _ => return review,
+    };
+
+    let earliest = (-delta).ceil().max(1.0 - review.interval) as i64;
+    let latest = delta.floor() as i64;
+    let count = |offset: &i64| {
+        let day = days.day(due + Duration::days(*offset));
+        due_counts.get(&day).copied().unwrap_or(0)
+    };
+    let fewest = (earliest..=latest).map(|offset| count(&offset)).min().unwrap_or(0);
+    let candidates: Vec<i64> = (earliest..=latest)
+        .filter(|offset| count(offset) == fewest)
+        .collect();
+
+    let offset = candidates[(seed_fraction(seed) * candidates.len() as f64) as usize];
+    review.interval += offset as f64;
+    review.due = Some(due + Duration::days(offset));
+    review
+}
+
+const FSRS_DECAY: f64 = -0.5;
+const FSRS_FACTOR: f64 = 19.0 / 81.0;
+
+pub const FSRS_DEFAULT_WEIGHTS: [f64; 17] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474, 0.1367, 1.0461,
+    2.1072, 0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+
+// Free Spaced Repetition Scheduler (FSRS-4.5). Tracks the stability (days
+// until recall probability drops to 90%) and difficulty (1-10) of each card
+// and picks the interval at which recall probability falls to the desired
+// retention.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fsrs {
+    #[serde(deserialize_with = "deserialize_weights")]
+    pub weights: Vec<f64>,
+    pub desired_retention: f64,
+    // Shared with SM-2, so they're configured there
+    #[serde(skip)]
+    pub learning_steps: Vec<u32>,
+    #[serde(skip)]
+    pub relearning_steps: Vec<u32>,
+    #[serde(skip)]
+    pub interval_modifier: f64,
+    #[serde(skip)]
+    pub maximum_interval: f64,
+}
+
+// Every weight is used, so a config with too few would panic on each review
+fn deserialize_weights<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    let weights = Vec::<f64>::deserialize(deserializer)?;
+    if weights.len() != FSRS_DEFAULT_WEIGHTS.len() {
+        return Err(serde::de::Error::invalid_length(
+            weights.len(),
+            &format!("{} FSRS weights", FSRS_DEFAULT_WEIGHTS.len()).as_str(),
+        ));
+    }
+    Ok(weights)
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Fsrs {
+            weights: FSRS_DEFAULT_WEIGHTS.to_vec(),
+            desired_retention: 0.9,
+            learning_steps: NEW_STEPS.to_vec(),
+            relearning_steps: RELEARNING_STEPS.to_vec(),
+            interval_modifier: INTERVAL_MODIFIER,
+            maximum_interval: MAXIMUM_INTERVAL,
+        }
+    }
+}
+
+fn score_value(score: &ReviewScore) -> f64 {
+    match score {
+        ReviewScore::Again => 1.0,
+        ReviewScore::Hard => 2.0,
+        ReviewScore::Good => 3.0,
+        ReviewScore::Easy => 4.0,
+    }
+}
+
+impl Fsrs {
+    pub fn retrievability(elapsed_days: f64, stability: f64) -> f64 {
+        (1.0 + FSRS_FACTOR * elapsed_days.max(0.0) / stability).powf(FSRS_DECAY)
+    }
+
+    // Intervals grow in step with stability, so scaling it scales the
+    // interval like SM-2's interval modifier does
+    pub fn next_interval(&self, stability: f64) -> f64 {
+        let interval = stability * self.interval_modifier / FSRS_FACTOR
+            * (self.desired_retention.powf(1.0 / FSRS_DECAY) - 1.0);
+        interval.round().max(1.0).min(self.maximum_interval)
+    }
+
+    pub fn initial_stability(&self, score: &ReviewScore) -> f64 {
+        self.weights[score_value(score) as usize - 1].max(0.1)
+    }
+
+    pub fn initial_difficulty(&self, score: &ReviewScore) -> f64 {
+        (self.weights[4] - (score_value(score) - 3.0) * self.weights[5]).clamp(1.0, 10.0)
+    }
+
+    pub fn next_difficulty(&self, difficulty: f64, score: &ReviewScore) -> f64 {
+        let next = difficulty - self.weights[6] * (score_value(score) - 3.0);
+        let reverted = self.weights[7] * self.initial_difficulty(&ReviewScore::Good)
+            + (1.0 - self.weights[7]) * next;
+        reverted.clamp(1.0, 10.0)
+    }
+
+    pub fn recall_stability(
+        &self,
+        difficulty: f64,
+        stability: f64,
+        retrievability: f64,
+        score: &ReviewScore,
+    ) -> f64 {
+        let w = &self.weights;
+        let hard_penalty = match score {
+            ReviewScore::Hard => w[15],
+            _ => 1.0,
+        };
+        let easy_bonus = match score {
+            ReviewScore::Easy => w[16],
+            _ => 1.0,
+        };
+        stability
+            * (w[8].exp()
+                * (11.0 - difficulty)
+                * stability.powf(-w[9])
+                * ((w[10] * (1.0 - retrievability)).exp() - 1.0)
+                * hard_penalty
+                * easy_bonus
+                + 1.0)
+    }
+
+    pub fn forget_stability(&self, difficulty: f64, stability: f64, retrievability: f64) -> f64 {
+        let w = &self.weights;
+        (w[11]
+            * difficulty.powf(-w[12])
+            * ((stability + 1.0).powf(w[13]) - 1.0)
+            * (w[14] * (1.0 - retrievability)).exp())
+        .min(stability)
+    }
+
+    // Cards first scheduled by another algorithm get a memory state derived
+    // from their current interval.
+    fn memory_state(&self, review: &Review, score: &ReviewScore) -> (f64, f64) {
+        match (review.stability, review.difficulty) {
+            (Some(stability), Some(difficulty)) => (stability, difficulty),
+            _ => match review.state {
+                ReviewState::New => (self.initial_stability(score), self.initial_difficulty(score)),
+                _ => (
+                    review.interval.max(0.1),
+                    self.initial_difficulty(&ReviewScore::Good),
+                ),
+            },
+        }
+    }
+
+    fn graduate(&self, mut review: Review, time: DateTime<Utc>) -> Review {
+        review.state = ReviewState::Learned;
+        review.steps = 0;
+        review.interval = self.next_interval(review.stability.unwrap());
+        if let Some(due) = days_due(time, review.interval) {
+            review.due = Some(due);
+        }
+        review
+    }
+
+    fn learning_step(
+        &self,
+        mut review: Review,
+        time: DateTime<Utc>,
+        score: ReviewScore,
+        steps: &[u32],
+    ) -> Review {
+        match next_step(steps, review.steps, &score) {
+            Some(step) => {
+                review.steps = step;
+                if let Some(due) = step_due(time, steps, step) {
                     review.due = Some(due);
                 }
                 review
             }
-            ReviewScore::Again => {
-                review.state = ReviewState::Relearning;
-                review.steps = 0;
-                review.interval = (review.interval * 0.7).max(MINIMUM_INTERVAL);
-                review.ease = (review.ease - 0.2).max(MINIMUM_EASE);
-                if let Some(due) = time.checked_add_signed(Duration::minutes(RELEARNING_STEPS[0].into())) {
-                    review.due = Some(due);
-                }
-                review
+            None => self.graduate(review, time),
+        }
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn schedule(&self, mut review: Review, time: DateTime<Utc>, score: ReviewScore) -> Review {
+        let (stability, difficulty) = self.memory_state(&review, &score);
+        match review.state {
+            ReviewState::New => {
+                review.stability = Some(stability);
+                review.difficulty = Some(difficulty);
+                self.learning_step(review, time, score, &self.learning_steps)
             }
-        },
-        ReviewState::Relearning => match score {
-            ReviewScore::Good | ReviewScore::Easy => {
-                review.state = ReviewState::Learned;
-                if let Some(due) = time.checked_add_signed(Duration::seconds(
-                    (review.interval * 24.0 * 60.0 * 60.0).round() as i64,
-                )) {
-                    review.due = Some(due);
+            ReviewState::Learned => {
+                // Days since the last review
+                let elapsed = match review.due {
+                    Some(due) => review.interval - (due - time).num_seconds() as f64 / 86400.0,
+                    None => review.interval,
+                };
+                let retrievability = Fsrs::retrievability(elapsed, stability);
+                review.difficulty = Some(self.next_difficulty(difficulty, &score));
+                match score {
+                    ReviewScore::Again => {
+                        review.stability =
+                            Some(self.forget_stability(difficulty, stability, retrievability));
+                        review.state = ReviewState::Relearning;
+                        review.steps = 0;
+                        review.lapses += 1;
+                        review.interval = self.next_interval(review.stability.unwrap());
+                        match step_due(time, &self.relearning_steps, 0) {
+                            Some(due) => {
+                                review.due = Some(due);
+                                review
+                            }
+                            None => self.graduate(review, time),
+                        }
+                    }
+                    _ => {
+                        review.stability = Some(self.recall_stability(
+                            difficulty,
+                            stability,
+                            retrievability,
+                            &score,
+                        ));
+                        review.interval = self.next_interval(review.stability.unwrap());
+                        if let Some(due) = days_due(time, review.interval) {
+                            review.due = Some(due);
+                        }
+                        review
+                    }
                 }
-                review
             }
-            ReviewScore::Hard | ReviewScore::Again=> {
-                review.steps = 0;
-                if let Some(due) = time.checked_add_signed(Duration::minutes(RELEARNING_STEPS[0].into())) {
-                    review.due = Some(due);
-                }
-                review
+            ReviewState::Relearning => {
+                review.stability = Some(stability);
+                review.difficulty = Some(difficulty);
+                self.learning_step(review, time, score, &self.relearning_steps)
             }
         }
     }
@@ -166,13 +701,24 @@ pub fn score_card(mut review: Review, time: DateTime<Utc>, score: ReviewScore) -
 #[cfg(test)]
 mod tests {
     use crate::review::{
-        score_card, Review, ReviewScore, ReviewState, EASY_BONUS, GRADUATION_INTERVAL, NEW_STEPS, RELEARNING_STEPS
+        format_interval, fuzz, fuzz_delta, fuzz_seed, limit_interval, load_balance, score_card, DayBoundary, DueCounts, Fsrs, Review, ReviewScore, ReviewState, Scheduler, Sm2, EASY_BONUS, EASY_INTERVAL, GRADUATION_INTERVAL, NEW_STEPS, RELEARNING_STEPS
     };
-    use chrono::{Duration, Utc};
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
 
     macro_rules! test_card {
         ($message:literal, $interval:literal, $ease:literal, $steps:literal, $state:expr,
      $score:expr,
+     $expected_interval:literal, $expected_ease:literal, $expected_steps:literal, $expected_duration:expr, $expected_state:expr) => {
+            test_card!(
+                scheduler: Sm2::default(),
+                $message, $interval, $ease, $steps, $state,
+                $score,
+                $expected_interval, $expected_ease, $expected_steps, $expected_duration, $expected_state
+            );
+        };
+        (scheduler: $scheduler:expr,
+     $message:literal, $interval:literal, $ease:literal, $steps:literal, $state:expr,
+     $score:expr,
      $expected_interval:literal, $expected_ease:literal, $expected_steps:literal, $expected_duration:expr, $expected_state:expr) => {
             let card = Review {
                 interval: $interval,
@@ -180,9 +726,10 @@ mod tests {
                 steps: $steps,
                 due: Some(Utc::now()),
                 state: $state,
+                ..Review::default()
             };
             let time = Utc::now();
-            let review = score_card(card, time, $score);
+            let review = $scheduler.schedule(card, time, $score);
             assert_eq!(
                 review.state, $expected_state,
                 "Test: {}\nIssue: Card state doesn't match",
@@ -224,7 +771,7 @@ mod tests {
             4.0,
             2.5,
             0,
-            Duration::days(GRADUATION_INTERVAL.into()),
+            Duration::days(EASY_INTERVAL.into()),
             ReviewState::Learned
         );
     }
@@ -402,6 +949,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn learned_card_scored_again_counts_lapse() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            lapses: 2,
+            ..Review::default()
+        };
+
+        assert_eq!(3, score_card(card.clone(), time, ReviewScore::Again).lapses);
+        assert_eq!(2, score_card(card.clone(), time, ReviewScore::Hard).lapses);
+        assert_eq!(3, Fsrs::default().schedule(card, time, ReviewScore::Again).lapses);
+
+        let relearning = Review {
+            due: Some(time),
+            state: ReviewState::Relearning,
+            lapses: 3,
+            ..Review::default()
+        };
+        assert_eq!(3, score_card(relearning, time, ReviewScore::Again).lapses);
+    }
+
     #[test]
     fn relearning_card_scored_again() {
         test_card!(
@@ -469,4 +1040,572 @@ mod tests {
             ReviewState::Learned
         );
     }
+
+    fn three_steps() -> Sm2 {
+        Sm2 {
+            learning_steps: vec![1, 10, 60],
+            relearning_steps: vec![5, 30],
+            ..Sm2::default()
+        }
+    }
+
+    fn no_steps() -> Sm2 {
+        Sm2 {
+            learning_steps: vec![],
+            relearning_steps: vec![],
+            ..Sm2::default()
+        }
+    }
+
+    #[test]
+    fn new_card_walks_learning_steps() {
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored good on the first step should move to the second",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Good,
+            1.0, 2.5, 1, Duration::minutes(10), ReviewState::New
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored good on the second step should move to the third",
+            1.0, 2.5, 1, ReviewState::New,
+            ReviewScore::Good,
+            1.0, 2.5, 2, Duration::minutes(60), ReviewState::New
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored good on the last step should graduate",
+            1.0, 2.5, 2, ReviewState::New,
+            ReviewScore::Good,
+            1.0, 2.5, 0, Duration::days(GRADUATION_INTERVAL.into()), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn new_card_scored_hard_repeats_step() {
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored hard on the first step should repeat it",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Hard,
+            1.0, 2.5, 0, Duration::minutes(1), ReviewState::New
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored hard on the second step should repeat it",
+            1.0, 2.5, 1, ReviewState::New,
+            ReviewScore::Hard,
+            1.0, 2.5, 1, Duration::minutes(10), ReviewState::New
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored hard on the last step should repeat it",
+            1.0, 2.5, 2, ReviewState::New,
+            ReviewScore::Hard,
+            1.0, 2.5, 2, Duration::minutes(60), ReviewState::New
+        );
+    }
+
+    #[test]
+    fn new_card_scored_again_restarts_steps() {
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored again on the last step should go back to the first",
+            1.0, 2.5, 2, ReviewState::New,
+            ReviewScore::Again,
+            1.0, 2.5, 0, Duration::minutes(1), ReviewState::New
+        );
+    }
+
+    #[test]
+    fn new_card_scored_easy_skips_steps() {
+        test_card!(
+            scheduler: three_steps(),
+            "New card scored easy on the second step should graduate",
+            1.0, 2.5, 1, ReviewState::New,
+            ReviewScore::Easy,
+            4.0, 2.5, 0, Duration::days(EASY_INTERVAL.into()), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn new_card_without_steps() {
+        test_card!(
+            scheduler: no_steps(),
+            "New card scored good without learning steps should graduate",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Good,
+            1.0, 2.5, 0, Duration::days(GRADUATION_INTERVAL.into()), ReviewState::Learned
+        );
+        test_card!(
+            scheduler: no_steps(),
+            "New card scored again without learning steps should graduate",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Again,
+            1.0, 2.5, 0, Duration::days(GRADUATION_INTERVAL.into()), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn single_learning_step() {
+        let scheduler = Sm2 {
+            learning_steps: vec![15],
+            ..Sm2::default()
+        };
+        test_card!(
+            scheduler: scheduler.clone(),
+            "New card scored good on the only step should graduate",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Good,
+            1.0, 2.5, 0, Duration::days(GRADUATION_INTERVAL.into()), ReviewState::Learned
+        );
+        test_card!(
+            scheduler: scheduler,
+            "New card scored hard on the only step should repeat it",
+            1.0, 2.5, 0, ReviewState::New,
+            ReviewScore::Hard,
+            1.0, 2.5, 0, Duration::minutes(15), ReviewState::New
+        );
+    }
+
+    #[test]
+    fn learned_card_lapses_into_first_relearning_step() {
+        test_card!(
+            scheduler: three_steps(),
+            "Learned card scored again should start the relearning steps",
+            2.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Again,
+            1.4, 2.3, 0, Duration::minutes(5), ReviewState::Relearning
+        );
+        test_card!(
+            scheduler: no_steps(),
+            "Learned card scored again without relearning steps should stay learned",
+            2.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Again,
+            1.4, 2.3, 0, Duration::seconds((1.4 * 60.0 * 60.0 * 24.0_f64).round() as i64), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn relearning_card_walks_relearning_steps() {
+        test_card!(
+            scheduler: three_steps(),
+            "Relearning card scored good on the first step should move to the second",
+            1.0, 2.5, 0, ReviewState::Relearning,
+            ReviewScore::Good,
+            1.0, 2.5, 1, Duration::minutes(30), ReviewState::Relearning
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "Relearning card scored hard on the second step should repeat it",
+            1.0, 2.5, 1, ReviewState::Relearning,
+            ReviewScore::Hard,
+            1.0, 2.5, 1, Duration::minutes(30), ReviewState::Relearning
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "Relearning card scored again on the second step should go back to the first",
+            1.0, 2.5, 1, ReviewState::Relearning,
+            ReviewScore::Again,
+            1.0, 2.5, 0, Duration::minutes(5), ReviewState::Relearning
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "Relearning card scored good on the last step should return to learned",
+            3.0, 2.5, 1, ReviewState::Relearning,
+            ReviewScore::Good,
+            3.0, 2.5, 0, Duration::days(3), ReviewState::Learned
+        );
+        test_card!(
+            scheduler: three_steps(),
+            "Relearning card scored easy on the first step should return to learned",
+            3.0, 2.5, 0, ReviewState::Relearning,
+            ReviewScore::Easy,
+            3.0, 2.5, 0, Duration::days(3), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn interval_modifier_scales_reviews() {
+        let scheduler = Sm2 {
+            interval_modifier: 0.8,
+            ..Sm2::default()
+        };
+        test_card!(
+            scheduler: scheduler.clone(),
+            "Learned card scored good should have its interval scaled by the modifier",
+            1.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Good,
+            2.0, 2.5, 0, Duration::days(2), ReviewState::Learned
+        );
+        test_card!(
+            scheduler: Sm2 { interval_modifier: 0.5, ..scheduler },
+            "Learned card scored hard should have its interval scaled by the modifier",
+            5.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Hard,
+            3.0, 2.35, 0, Duration::days(3), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn hard_multiplier_is_configurable() {
+        test_card!(
+            scheduler: Sm2 { hard_multiplier: 1.5, ..Sm2::default() },
+            "Learned card scored hard should use the configured multiplier",
+            2.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Hard,
+            3.0, 2.35, 0, Duration::days(3), ReviewState::Learned
+        );
+    }
+
+    #[test]
+    fn maximum_interval_caps_reviews() {
+        let scheduler = Sm2 {
+            maximum_interval: 180.0,
+            ..Sm2::default()
+        };
+        test_card!(
+            scheduler: scheduler.clone(),
+            "Learned card scored good should not go past the maximum interval",
+            100.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Good,
+            180.0, 2.5, 0, Duration::days(180), ReviewState::Learned
+        );
+        test_card!(
+            scheduler: scheduler,
+            "Learned card scored easy should not go past the maximum interval",
+            100.0, 2.5, 0, ReviewState::Learned,
+            ReviewScore::Easy,
+            180.0, 2.65, 0, Duration::days(180), ReviewState::Learned
+        );
+
+        let fsrs = Fsrs {
+            maximum_interval: 180.0,
+            ..Fsrs::default()
+        };
+        assert_eq!(180.0, fsrs.next_interval(1000.0));
+    }
+
+    #[test]
+    fn limit_interval_pulls_in_due_date() {
+        let time = Utc::now();
+        let review = Review {
+            due: Some(time + Duration::days(183)),
+            interval: 183.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+
+        let limited = limit_interval(review, 180.0);
+
+        assert_eq!(180.0, limited.interval);
+        assert_eq!(time + Duration::days(180), limited.due.unwrap());
+    }
+
+    #[test]
+    fn fuzz_leaves_short_intervals() {
+        let review = score_card(Review::default(), Utc::now(), ReviewScore::Good);
+        assert_eq!(review, fuzz(review.clone(), fuzz_seed("123", 1, 1)));
+
+        let learned = Review {
+            due: Some(Utc::now()),
+            interval: 2.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        assert_eq!(learned, fuzz(learned.clone(), fuzz_seed("123", 1, 1)));
+    }
+
+    #[test]
+    fn fuzz_stays_within_range() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let review = score_card(card, time, ReviewScore::Good);
+        let delta = fuzz_delta(review.interval);
+        assert!((delta - 3.225).abs() < 1e-9);
+
+        let intervals: Vec<f64> = (0..50)
+            .map(|note| {
+                let fuzzed = fuzz(review.clone(), fuzz_seed(&note.to_string(), 1, 3));
+                let due = fuzzed.due.unwrap().signed_duration_since(time);
+                assert_eq!(
+                    (fuzzed.interval * 24.0 * 60.0 * 60.0).round() as i64,
+                    due.num_seconds(),
+                    "Due date should follow the fuzzed interval"
+                );
+                fuzzed.interval
+            })
+            .collect();
+
+        assert!(intervals
+            .iter()
+            .all(|interval| (interval - review.interval).abs() <= delta));
+        assert!(intervals.iter().any(|interval| *interval < review.interval));
+        assert!(intervals.iter().any(|interval| *interval > review.interval));
+    }
+
+    #[test]
+    fn fuzz_is_deterministic() {
+        let card = Review {
+            due: Some(Utc::now()),
+            interval: 30.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        assert_eq!(
+            fuzz(card.clone(), fuzz_seed("123", 1, 4)),
+            fuzz(card.clone(), fuzz_seed("123", 1, 4))
+        );
+        assert_ne!(
+            fuzz(card.clone(), fuzz_seed("123", 1, 4)),
+            fuzz(card, fuzz_seed("123", 1, 5))
+        );
+    }
+
+    #[test]
+    fn load_balance_picks_quietest_day() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let review = score_card(card, time, ReviewScore::Good);
+        let due = review.due.unwrap();
+
+        // Every day in the range is busy except two days after the due date
+        let due_counts: DueCounts = (-5..=5)
+            .map(|offset| {
+                let day = (due + Duration::days(offset)).date_naive();
+                (day, if offset == 2 { 1 } else { 10 })
+            })
+            .collect();
+
+        for note in 0..10 {
+            let balanced = load_balance(
+                review.clone(),
+                fuzz_seed(&note.to_string(), 1, 2),
+                &due_counts,
+                DayBoundary::default(),
+            );
+            assert_eq!(review.interval + 2.0, balanced.interval);
+            assert_eq!(due + Duration::days(2), balanced.due.unwrap());
+        }
+    }
+
+    #[test]
+    fn load_balance_spreads_ties() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let review = score_card(card, time, ReviewScore::Good);
+        let delta = fuzz_delta(review.interval);
+
+        let intervals: Vec<f64> = (0..50)
+            .map(|note| {
+                let seed = fuzz_seed(&note.to_string(), 1, 2);
+                load_balance(review.clone(), seed, &DueCounts::new(), DayBoundary::default()).interval
+            })
+            .collect();
+
+        assert!(intervals.iter().all(|interval| (interval - review.interval).abs() <= delta));
+        assert!(intervals.iter().any(|interval| *interval != review.interval));
+        assert_eq!(
+            load_balance(review.clone(), 3, &DueCounts::new(), DayBoundary::default()),
+            load_balance(review, 3, &DueCounts::new(), DayBoundary::default())
+        );
+    }
+
+    #[test]
+    fn fsrs_retrievability_at_stability() {
+        let fsrs = Fsrs::default();
+        assert!((Fsrs::retrievability(10.0, 10.0) - 0.9).abs() < 1e-9);
+        assert_eq!(10.0, fsrs.next_interval(10.0));
+    }
+
+    #[test]
+    fn fsrs_new_card_scored_good() {
+        let fsrs = Fsrs::default();
+        let time = Utc::now();
+        let review = fsrs.schedule(Review::default(), time, ReviewScore::Good);
+
+        assert_eq!(ReviewState::New, review.state);
+        assert_eq!(1, review.steps);
+        assert_eq!(Some(fsrs.weights[2]), review.stability);
+        assert_eq!(Some(fsrs.initial_difficulty(&ReviewScore::Good)), review.difficulty);
+        assert_eq!(
+            Duration::minutes(NEW_STEPS[1].into()),
+            review.due.unwrap().signed_duration_since(time)
+        );
+
+        let review = fsrs.schedule(review, time, ReviewScore::Good);
+        assert_eq!(ReviewState::Learned, review.state);
+        assert_eq!(fsrs.next_interval(fsrs.weights[2]), review.interval);
+    }
+
+    #[test]
+    fn fsrs_new_card_scored_easy() {
+        let fsrs = Fsrs::default();
+        let time = Utc::now();
+        let review = fsrs.schedule(Review::default(), time, ReviewScore::Easy);
+
+        assert_eq!(ReviewState::Learned, review.state);
+        assert_eq!(Some(fsrs.weights[3]), review.stability);
+        assert_eq!(fsrs.next_interval(fsrs.weights[3]), review.interval);
+        assert_eq!(
+            Duration::days(review.interval as i64),
+            review.due.unwrap().signed_duration_since(time)
+        );
+    }
+
+    #[test]
+    fn fsrs_relearned_interval_is_modified() {
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 100.0,
+            state: ReviewState::Learned,
+            stability: Some(100.0),
+            difficulty: Some(5.0),
+            ..Review::default()
+        };
+        let unmodified = Fsrs::default();
+        let fsrs = Fsrs { interval_modifier: 0.5, ..Fsrs::default() };
+
+        let lapsed = fsrs.schedule(card.clone(), time, ReviewScore::Again);
+        let relearned = fsrs.schedule(lapsed.clone(), time, ReviewScore::Good);
+        assert_eq!(ReviewState::Learned, relearned.state);
+        assert_eq!(unmodified.next_interval(relearned.stability.unwrap() * 0.5), relearned.interval);
+        assert_ne!(unmodified.next_interval(relearned.stability.unwrap()), relearned.interval);
+
+        // Without relearning steps the card goes straight back to review
+        let no_steps = Fsrs { relearning_steps: vec![], ..fsrs };
+        let lapsed = no_steps.schedule(card, time, ReviewScore::Again);
+        assert_eq!(ReviewState::Learned, lapsed.state);
+        assert_eq!(unmodified.next_interval(lapsed.stability.unwrap() * 0.5), lapsed.interval);
+    }
+
+    #[test]
+    fn fsrs_learned_card() {
+        let fsrs = Fsrs::default();
+        let time = Utc::now();
+        let card = Review {
+            due: Some(time),
+            interval: 10.0,
+            state: ReviewState::Learned,
+            stability: Some(10.0),
+            difficulty: Some(5.0),
+            ..Review::default()
+        };
+
+        let good = fsrs.schedule(card.clone(), time, ReviewScore::Good);
+        assert_eq!(ReviewState::Learned, good.state);
+        assert!(good.stability.unwrap() > 10.0, "Recall should increase stability");
+        assert!(good.interval > 10.0);
+
+        let hard = fsrs.schedule(card.clone(), time, ReviewScore::Hard);
+        let easy = fsrs.schedule(card.clone(), time, ReviewScore::Easy);
+        assert!(hard.interval <= good.interval && good.interval <= easy.interval);
+        assert!(hard.difficulty.unwrap() > 5.0 && easy.difficulty.unwrap() < 5.0);
+
+        let again = fsrs.schedule(card, time, ReviewScore::Again);
+        assert_eq!(ReviewState::Relearning, again.state);
+        assert!(again.stability.unwrap() < 10.0, "Lapse should decrease stability");
+        assert_eq!(
+            Duration::minutes(RELEARNING_STEPS[0].into()),
+            again.due.unwrap().signed_duration_since(time)
+        );
+
+        let relearned = fsrs.schedule(again.clone(), time, ReviewScore::Good);
+        assert_eq!(ReviewState::Learned, relearned.state);
+        assert_eq!(again.stability, relearned.stability);
+    }
+
+    #[test]
+    fn fsrs_weights_must_all_be_given() {
+        assert!(serde_yaml::from_str::<Fsrs>("weights: [0.4, 1.4, 3.7]").is_err());
+        assert!(serde_yaml::from_str::<Fsrs>(&format!("weights: {:?}", [1.0; 18])).is_err());
+
+        let fsrs: Fsrs = serde_yaml::from_str(&format!("weights: {:?}", [1.0; 17])).unwrap();
+        assert_eq!(vec![1.0; 17], fsrs.weights);
+        assert_eq!(Fsrs::default().weights, serde_yaml::from_str::<Fsrs>("desired_retention: 0.8").unwrap().weights);
+    }
+
+    #[test]
+    fn day_boundary_rolls_over_at_the_configured_hour() {
+        // UTC+2 with days starting at 4am local time, i.e. 2am UTC
+        let days = DayBoundary::new(Duration::hours(2), 4);
+
+        let before = Utc.with_ymd_and_hms(2023, 3, 10, 1, 59, 0).unwrap();
+        let after = Utc.with_ymd_and_hms(2023, 3, 10, 2, 0, 0).unwrap();
+
+        assert_eq!(NaiveDate::from_ymd_opt(2023, 3, 9).unwrap(), days.day(before));
+        assert_eq!(NaiveDate::from_ymd_opt(2023, 3, 10).unwrap(), days.day(after));
+        assert_eq!(after, days.start(days.day(after)));
+        assert_eq!(after, days.next_day(before));
+    }
+
+    #[test]
+    fn day_intervals_count_calendar_days() {
+        let days = DayBoundary::new(Duration::zero(), 4);
+        // Graduated at 11pm, due the next day from 4am rather than from 11pm
+        let time = Utc.with_ymd_and_hms(2023, 3, 10, 23, 0, 0).unwrap();
+        let card = Review {
+            due: Some(time),
+            steps: 1,
+            ..Review::default()
+        };
+
+        let review = days.round_due(score_card(card, time, ReviewScore::Good));
+
+        assert_eq!(ReviewState::Learned, review.state);
+        assert_eq!(Some(Utc.with_ymd_and_hms(2023, 3, 11, 4, 0, 0).unwrap()), review.due);
+        assert!(!days.is_due(&review, Utc.with_ymd_and_hms(2023, 3, 11, 3, 59, 0).unwrap()));
+        assert!(days.is_due(&review, Utc.with_ymd_and_hms(2023, 3, 11, 4, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn learning_steps_stay_precise() {
+        let days = DayBoundary::new(Duration::zero(), 4);
+        let time = Utc.with_ymd_and_hms(2023, 3, 10, 23, 0, 0).unwrap();
+
+        let review = days.round_due(score_card(Review::default(), time, ReviewScore::Good));
+
+        assert_eq!(Some(time + Duration::minutes(10)), review.due);
+        assert!(!days.is_due(&review, time + Duration::minutes(9)));
+        assert!(days.is_due(&review, time + Duration::minutes(11)));
+    }
+
+    #[test]
+    fn intervals_in_short_form() {
+        let time = Utc::now();
+        let learned = |interval: f64| Review {
+            due: Some(time + Duration::days(interval as i64)),
+            interval,
+            state: ReviewState::Learned,
+            ..Review::default()
+        };
+        let learning = |minutes: i64| Review {
+            due: Some(time + Duration::minutes(minutes)),
+            ..Review::default()
+        };
+
+        assert_eq!("1m", format_interval(&learning(0), time));
+        assert_eq!("10m", format_interval(&learning(10), time));
+        assert_eq!("2h", format_interval(&learning(120), time));
+        assert_eq!("3d", format_interval(&learned(3.0), time));
+        assert_eq!("1.5mo", format_interval(&learned(45.0), time));
+        assert_eq!("2.0y", format_interval(&learned(730.0), time));
+    }
 }
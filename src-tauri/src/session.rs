@@ -0,0 +1,460 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Duration, Utc};
+use tauri::State;
+
+use crate::card::{self, Card, QueueKind};
+use crate::config;
+use crate::context::Context;
+use crate::deck;
+use crate::history;
+use crate::review::{DayBoundary, DueCounts, Review, ReviewScore};
+
+// Once nothing else is left, learning cards are shown this far ahead of time
+// rather than making the user wait
+const LEARN_AHEAD_MINUTES: i64 = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionCounts {
+    pub new: usize,
+    pub learning: usize,
+    pub review: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub card: Option<Card>,
+    pub counts: SessionCounts,
+    // Review log lines that couldn't be read and were left out, by log
+    pub unreadable_lines: BTreeMap<String, Vec<usize>>,
+}
+
+// Which list of the session a card was in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Place {
+    Queue,
+    Learning,
+}
+
+// A card as it was before it was answered and where it was, along with the
+// siblings that answering it took out of the session and when the answer made
+// it due
+struct Answered {
+    card: Card,
+    place: Option<Place>,
+    siblings: Vec<(Card, Place)>,
+    due: Option<DateTime<Utc>>,
+}
+
+pub struct ReviewSession {
+    days: DayBoundary,
+    // New, review and due learning cards, in the order they're shown
+    queue: VecDeque<Card>,
+    // Learning cards waiting for their step to run out, soonest first
+    learning: Vec<Card>,
+    // Most recent last
+    answered: Vec<Answered>,
+    // The card being shown and when it was first shown, to time the answer
+    shown: Option<(Card, DateTime<Utc>)>,
+    unreadable_lines: BTreeMap<String, Vec<usize>>,
+    // Cards due on each day, by deck. Counted once when the session starts,
+    // then kept up to date as cards are answered.
+    due_counts: BTreeMap<String, DueCounts>,
+}
+
+// Managed by tauri, holds the session that is being studied, if any
+#[derive(Default)]
+pub struct SessionState(pub Mutex<Option<ReviewSession>>);
+
+impl ReviewSession {
+    pub fn start(context: &Context, deck: &str, time: DateTime<Utc>) -> Result<Self, String> {
+        let days = config::load_day_boundary(&context.get_collection_path(), time)?;
+        let mut session = ReviewSession {
+            days,
+            queue: card::get_cards_to_review(context, deck)?.into(),
+            learning: vec![],
+            answered: vec![],
+            shown: None,
+            unreadable_lines: BTreeMap::new(),
+            due_counts: BTreeMap::new(),
+        };
+        for deck in deck::get_deck_tree(&context.get_collection_path(), deck) {
+            session.unreadable_lines.extend(history::find_unreadable_lines(&context.get_collection_path(), &deck));
+            session.due_counts.insert(deck.clone(), card::get_due_counts(&context.get_collection_path(), &deck, days));
+            for card in card::get_deck_cards(context, &deck)? {
+                if !Review::from(card.clone()).is_hidden(time) && card.due().is_some_and(|due| due >= time) {
+                    session.wait(card, time);
+                }
+            }
+        }
+        Ok(session)
+    }
+
+    // Learning cards due later today wait to be shown again
+    fn wait(&mut self, card: Card, time: DateTime<Utc>) {
+        let due = match card.due() {
+            Some(due) if card.queue_kind() == QueueKind::Learning => due,
+            _ => return,
+        };
+        if self.days.day(due) == self.days.day(time) {
+            self.put_back(card, Some(Place::Learning));
+        }
+    }
+
+    // Learning cards are kept in order of when they're due, anything else goes
+    // to the front of the queue
+    fn put_back(&mut self, card: Card, place: Option<Place>) {
+        match place {
+            Some(Place::Learning) => {
+                let index = self.learning.partition_point(|x| x.due() <= card.due());
+                self.learning.insert(index, card);
+            }
+            _ => self.queue.push_front(card),
+        }
+    }
+
+    fn move_due(&mut self, deck: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) {
+        let days = self.days;
+        let due_counts = self.due_counts.entry(deck.to_string()).or_default();
+        if let Some(count) = from.and_then(|due| due_counts.get_mut(&days.day(due))) {
+            *count = count.saturating_sub(1);
+        }
+        if let Some(due) = to {
+            *due_counts.entry(days.day(due)).or_default() += 1;
+        }
+    }
+
+    // Returns where the card was, if it was in the session
+    fn remove(&mut self, card: &Card) -> Option<Place> {
+        if let Some(index) = self.queue.iter().position(|x| x.is_same(card)) {
+            self.queue.remove(index);
+            return Some(Place::Queue);
+        }
+        let index = self.learning.iter().position(|x| x.is_same(card))?;
+        self.learning.remove(index);
+        Some(Place::Learning)
+    }
+
+    // Learning cards come first once due. With nothing else left, they're
+    // shown up to LEARN_AHEAD_MINUTES early.
+    pub fn next_card(&self, time: DateTime<Utc>) -> Option<Card> {
+        let due_by = |card: &&Card, limit: DateTime<Utc>| card.due().map_or(true, |due| due <= limit);
+        match self.learning.first() {
+            Some(card) if due_by(&card, time) => Some(card.clone()),
+            _ => match self.queue.front() {
+                Some(card) => Some(card.clone()),
+                None => self
+                    .learning
+                    .first()
+                    .filter(|card| due_by(card, time + Duration::minutes(LEARN_AHEAD_MINUTES)))
+                    .cloned(),
+            },
+        }
+    }
+
+    pub fn counts(&self) -> SessionCounts {
+        let mut counts = SessionCounts {
+            learning: self.learning.len(),
+            ..SessionCounts::default()
+        };
+        for card in &self.queue {
+            match card.queue_kind() {
+                QueueKind::New => counts.new += 1,
+                QueueKind::Learning => counts.learning += 1,
+                QueueKind::Review => counts.review += 1,
+            }
+        }
+        counts
+    }
+
+    // Also marks the next card as shown, unless it already is
+    pub fn status(&mut self, time: DateTime<Utc>) -> SessionStatus {
+        let card = self.next_card(time);
+        let showing = match (&card, &self.shown) {
+            (Some(card), Some((shown, _))) => card.is_same(shown),
+            _ => false,
+        };
+        if !showing {
+            self.shown = card.clone().map(|card| (card, time));
+        }
+        SessionStatus {
+            card,
+            counts: self.counts(),
+            unreadable_lines: self.unreadable_lines.clone(),
+        }
+    }
+
+    pub fn answer(&mut self, context: &Context, card: Card, score: ReviewScore) -> Result<Card, String> {
+        let time = Utc::now();
+        let answer_time = match &self.shown {
+            Some((shown, shown_at)) if shown.is_same(&card) => Some(time - *shown_at),
+            _ => None,
+        };
+        let none_due = DueCounts::new();
+        let due_counts = self.due_counts.get(card.deck_id()).unwrap_or(&none_due);
+        // The card stays in the session until the answer is logged
+        let answered = card::answer_card(context, card.clone(), score, answer_time, due_counts)?;
+        self.shown = None;
+        let place = self.remove(&card);
+        self.move_due(card.deck_id(), card.due(), answered.due());
+
+        // Siblings buried by the answer leave the queue until tomorrow
+        let mut siblings = vec![];
+        for sibling in card::get_siblings(context, &answered) {
+            let queued = self.queue.iter().chain(self.learning.iter()).find(|x| x.is_same(&sibling)).cloned();
+            if let Some(queued) = queued {
+                if Review::from(sibling).is_hidden(time) {
+                    if let Some(place) = self.remove(&queued) {
+                        siblings.push((queued, place));
+                    }
+                }
+            }
+        }
+
+        self.answered.push(Answered { card, place, siblings, due: answered.due() });
+        self.wait(answered.clone(), time);
+        Ok(answered)
+    }
+
+    // Takes back the latest answer in this session and puts the card back
+    // where it was, so a card from the queue is shown again
+    pub fn undo(&mut self, context: &Context) -> Result<Card, String> {
+        let card = match self.answered.last() {
+            Some(answered) => answered.card.clone(),
+            None => return Err("Nothing to undo".to_string()),
+        };
+        let restored = card::undo(context, card)?;
+        let Answered { place, siblings, due, .. } = self.answered.pop().unwrap();
+        self.shown = None;
+        self.move_due(restored.deck_id(), due, restored.due());
+
+        self.remove(&restored);
+        for (sibling, place) in siblings.into_iter().rev() {
+            self.put_back(sibling, Some(place));
+        }
+        self.put_back(restored.clone(), place);
+        Ok(restored)
+    }
+}
+
+fn with_session<T>(
+    session: &SessionState,
+    f: impl FnOnce(&mut ReviewSession) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut guard = session.0.lock().map_err(|err| err.to_string())?;
+    match guard.as_mut() {
+        Some(session) => f(session),
+        None => Err("No review session has been started".to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn start_session(
+    state: State<'_, Context>,
+    session: State<'_, SessionState>,
+    deck: &str,
+) -> Result<SessionStatus, String> {
+    let mut started = ReviewSession::start(state.inner(), deck, Utc::now())?;
+    let status = started.status(Utc::now());
+    *session.inner().0.lock().map_err(|err| err.to_string())? = Some(started);
+    Ok(status)
+}
+
+#[tauri::command]
+pub fn next_card(session: State<'_, SessionState>) -> Result<SessionStatus, String> {
+    with_session(session.inner(), |session| Ok(session.status(Utc::now())))
+}
+
+#[tauri::command]
+pub fn review_card(
+    state: State<'_, Context>,
+    session: State<'_, SessionState>,
+    card: Card,
+    score: ReviewScore,
+) -> Result<SessionStatus, String> {
+    with_session(session.inner(), |session| {
+        session.answer(state.inner(), card, score)?;
+        Ok(session.status(Utc::now()))
+    })
+}
+
+#[tauri::command]
+pub fn undo_review(state: State<'_, Context>, session: State<'_, SessionState>) -> Result<SessionStatus, String> {
+    with_session(session.inner(), |session| {
+        session.undo(state.inner())?;
+        Ok(session.status(Utc::now()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::card::QueueKind;
+    use crate::config::DECK_CONFIG_FILE;
+    use crate::context::Context;
+    use crate::history::{append_entry, get_reviews_path, read_log, ReviewLogEntry};
+    use crate::review::{Review, ReviewScore, ReviewState};
+    use crate::session::{ReviewSession, SessionCounts};
+    use crate::test_helpers::scaffold_deck;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn learning_cards_come_back_in_the_session() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+        assert_eq!(SessionCounts { new: 2, learning: 0, review: 0 }, session.counts());
+
+        let first = session.next_card(Utc::now()).unwrap();
+        session.answer(&context, first.clone(), ReviewScore::Again).unwrap();
+        assert_eq!(SessionCounts { new: 1, learning: 1, review: 0 }, session.counts());
+
+        let second = session.next_card(Utc::now()).unwrap();
+        assert!(!second.is_same(&first), "The learning card isn't due yet");
+        session.answer(&context, second, ReviewScore::Good).unwrap();
+        assert_eq!(SessionCounts { new: 0, learning: 2, review: 0 }, session.counts());
+
+        // Nothing else is left, so the 1 minute step is shown early
+        let next = session.next_card(Utc::now()).unwrap();
+        assert!(next.is_same(&first));
+        assert_eq!(QueueKind::Learning, next.queue_kind());
+
+        session.answer(&context, next, ReviewScore::Easy).unwrap();
+        assert_eq!(SessionCounts { new: 0, learning: 1, review: 0 }, session.counts());
+        assert!(session.next_card(Utc::now()).is_some());
+        assert!(session.next_card(Utc::now() - Duration::hours(1)).is_none());
+    }
+
+    #[test]
+    fn session_picks_up_learning_cards_due_later_today() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let learning = Review {
+            due: Some(Utc::now() + Duration::minutes(5)),
+            state: ReviewState::Relearning,
+            ..Review::default()
+        };
+        let entry = ReviewLogEntry::new(1, Utc::now(), ReviewScore::Again, Review::default(), learning);
+        append_entry(&get_reviews_path(collection, "testdeck").join("123.jsonl"), &entry).unwrap();
+        let context = Context::from(collection);
+
+        let session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+
+        assert_eq!(SessionCounts { new: 1, learning: 1, review: 0 }, session.counts());
+    }
+
+    #[test]
+    fn undo_puts_the_card_back_in_front() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+
+        let first = session.next_card(Utc::now()).unwrap();
+        session.answer(&context, first.clone(), ReviewScore::Again).unwrap();
+        let second = session.next_card(Utc::now()).unwrap();
+        session.answer(&context, second.clone(), ReviewScore::Good).unwrap();
+
+        assert_eq!(second, session.undo(&context).unwrap());
+        assert_eq!(Some(second), session.next_card(Utc::now()));
+        assert_eq!(first, session.undo(&context).unwrap());
+        assert_eq!(SessionCounts { new: 2, learning: 0, review: 0 }, session.counts());
+        assert!(session.undo(&context).is_err());
+    }
+
+    #[test]
+    fn undo_puts_learning_cards_back_in_learning() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+        for _ in 0..2 {
+            let card = session.next_card(Utc::now()).unwrap();
+            session.answer(&context, card, ReviewScore::Again).unwrap();
+        }
+        assert!(session.queue.is_empty());
+
+        let learning = session.next_card(Utc::now()).unwrap();
+        session.answer(&context, learning.clone(), ReviewScore::Easy).unwrap();
+        assert_eq!(1, session.learning.len());
+
+        assert!(session.undo(&context).unwrap().is_same(&learning));
+        assert!(session.queue.is_empty(), "The card didn't come from the queue");
+        assert_eq!(2, session.learning.len());
+        assert!(session.learning[0].is_same(&learning));
+    }
+
+    #[test]
+    fn failed_answers_keep_the_card() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+        let deck_config = Path::new(collection).join("testdeck").join(DECK_CONFIG_FILE);
+
+        let first = session.next_card(Utc::now()).unwrap();
+        fs::write(&deck_config, "leech_threshold: [\n").unwrap();
+        assert!(session.answer(&context, first.clone(), ReviewScore::Good).is_err());
+        assert_eq!(SessionCounts { new: 2, learning: 0, review: 0 }, session.counts());
+        assert_eq!(Some(first.clone()), session.next_card(Utc::now()));
+
+        fs::remove_file(&deck_config).unwrap();
+        session.answer(&context, first.clone(), ReviewScore::Good).unwrap();
+        assert_eq!(SessionCounts { new: 1, learning: 1, review: 0 }, session.counts());
+    }
+
+    #[test]
+    fn due_counts_follow_answers() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+        let due_total = |session: &ReviewSession| session.due_counts["testdeck"].values().sum::<usize>();
+        assert_eq!(0, due_total(&session));
+
+        let first = session.next_card(Utc::now()).unwrap();
+        let answered = session.answer(&context, first, ReviewScore::Easy).unwrap();
+        let day = session.days.day(answered.due().unwrap());
+        assert_eq!(Some(&1), session.due_counts["testdeck"].get(&day));
+
+        session.undo(&context).unwrap();
+        assert_eq!(0, due_total(&session));
+    }
+
+    #[test]
+    fn answer_time_is_recorded_and_capped() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_deck(collection);
+        let context = Context::from(collection);
+        let mut session = ReviewSession::start(&context, "testdeck", Utc::now()).unwrap();
+
+        let first = session.status(Utc::now() - Duration::seconds(5)).card.unwrap();
+        // Asking again doesn't restart the clock
+        session.status(Utc::now());
+        session.answer(&context, first.clone(), ReviewScore::Good).unwrap();
+
+        let second = session.status(Utc::now() - Duration::minutes(10)).card.unwrap();
+        session.answer(&context, second.clone(), ReviewScore::Good).unwrap();
+
+        // New cards are shown in creation order, so 123 first
+        let answer_millis = |note_id: &str| {
+            let log = read_log(&get_reviews_path(collection, "testdeck").join(format!("{}.jsonl", note_id)));
+            log[0].answer_millis.unwrap()
+        };
+        assert!((5000..6000).contains(&answer_millis("123")));
+        assert_eq!(60000, answer_millis("456"));
+    }
+}
@@ -0,0 +1,357 @@
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tauri::State;
+
+use crate::config::{self, SchedulerConfig};
+use crate::context::Context;
+use crate::review::{DayBoundary, DueCounts, Fsrs, Review, ReviewScore, ReviewState};
+
+// Stops a card that keeps failing from being answered forever in one day
+const MAXIMUM_ANSWERS_PER_DAY: usize = 20;
+// Every card is stepped through every day, so these keep a simulation from
+// running for hours
+const MAXIMUM_DAYS: u32 = 3650;
+const MAXIMUM_DECK_SIZE: usize = 100_000;
+
+// How the simulated user remembers. Recall follows the FSRS forgetting curve
+// with these weights, whatever scheduler is being simulated.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecallModel {
+    pub memory: Fsrs,
+    // Chance of answering Again the first time a card is seen
+    pub first_again_chance: f64,
+    // Chances of Hard and Easy when a card is recalled, Good otherwise
+    pub hard_chance: f64,
+    pub easy_chance: f64,
+    pub seconds_per_new: f64,
+    pub seconds_per_review: f64,
+}
+
+impl Default for RecallModel {
+    fn default() -> Self {
+        RecallModel {
+            memory: Fsrs::default(),
+            first_again_chance: 0.3,
+            hard_chance: 0.15,
+            easy_chance: 0.15,
+            seconds_per_new: 20.0,
+            seconds_per_review: 8.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub deck_size: usize,
+    pub new_per_day: usize,
+    pub days: u32,
+    pub scheduler: SchedulerConfig,
+    pub recall: RecallModel,
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            deck_size: 1000,
+            new_per_day: 20,
+            days: 90,
+            scheduler: SchedulerConfig::default(),
+            recall: RecallModel::default(),
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedDay {
+    pub new: usize,
+    // Every answer given that day, first answers to new cards included
+    pub reviews: usize,
+    pub lapses: usize,
+    pub seconds: f64,
+    // Average chance of recalling each card seen so far, at the end of the day
+    pub retention: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Simulation {
+    pub days: Vec<SimulatedDay>,
+    pub total_reviews: usize,
+    pub total_seconds: f64,
+    // Retention at the end of the last day
+    pub retention: f64,
+}
+
+// Moves a card from the day it was due on to the day it is due on now
+fn move_due(due_counts: &mut DueCounts, days: DayBoundary, before: &Review, after: &Review) {
+    if let Some(due) = before.due {
+        if let Some(count) = due_counts.get_mut(&days.day(due)) {
+            *count = count.saturating_sub(1);
+        }
+    }
+    if let Some(due) = after.due {
+        *due_counts.entry(days.day(due)).or_insert(0) += 1;
+    }
+}
+
+// What the scheduler knows about a card, and what the user actually remembers
+struct SimulatedCard {
+    review: Review,
+    stability: f64,
+    difficulty: f64,
+    last_review: DateTime<Utc>,
+}
+
+impl SimulatedCard {
+    fn retrievability(&self, time: DateTime<Utc>) -> f64 {
+        let elapsed = (time - self.last_review).num_seconds() as f64 / 86400.0;
+        Fsrs::retrievability(elapsed, self.stability)
+    }
+}
+
+fn pick_score(recall: &RecallModel, recall_chance: f64, rng: &mut StdRng) -> ReviewScore {
+    if rng.gen::<f64>() >= recall_chance {
+        return ReviewScore::Again;
+    }
+    let roll = rng.gen::<f64>();
+    if roll < recall.hard_chance {
+        ReviewScore::Hard
+    } else if roll < recall.hard_chance + recall.easy_chance {
+        ReviewScore::Easy
+    } else {
+        ReviewScore::Good
+    }
+}
+
+// Simulates studying a deck from scratch, one day at a time, starting on the
+// day `start` falls on. The same config and seed always give the same result.
+pub fn simulate(config: &SimulationConfig, start: DateTime<Utc>, days: DayBoundary) -> Simulation {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let recall = &config.recall;
+    let first_day = days.day(start);
+    let mut cards: Vec<SimulatedCard> = vec![];
+    let mut due_counts = DueCounts::new();
+    let mut simulation = Simulation::default();
+
+    for day in 0..config.days {
+        let day_start = days.start(first_day + Duration::days(day.into()));
+        let day_end = days.next_day(day_start);
+        let mut today = SimulatedDay::default();
+
+        let introduced = config.new_per_day.min(config.deck_size - cards.len());
+        for _ in 0..introduced {
+            let score = if rng.gen::<f64>() < recall.first_again_chance {
+                ReviewScore::Again
+            } else {
+                pick_score(recall, 1.0, &mut rng)
+            };
+            let review = config.scheduler.schedule(
+                Review::default(),
+                day_start,
+                score.clone(),
+                rng.gen(),
+                &due_counts,
+                days,
+            );
+            move_due(&mut due_counts, days, &Review::default(), &review);
+            cards.push(SimulatedCard {
+                review,
+                stability: recall.memory.initial_stability(&score),
+                difficulty: recall.memory.initial_difficulty(&score),
+                last_review: day_start,
+            });
+            today.new += 1;
+            today.reviews += 1;
+            today.seconds += recall.seconds_per_new;
+        }
+
+        for card in cards.iter_mut() {
+            for _ in 0..MAXIMUM_ANSWERS_PER_DAY {
+                let time = match card.review.due {
+                    Some(due) if due < day_end => due.max(day_start),
+                    _ => break,
+                };
+                let elapsed = (time - card.last_review).num_seconds() as f64 / 86400.0;
+                let retrievability = card.retrievability(time);
+                let score = pick_score(recall, retrievability, &mut rng);
+
+                // Like the optimizer, reviews on the same day as the previous
+                // one don't change what is remembered
+                if elapsed >= 1.0 {
+                    card.stability = match score {
+                        ReviewScore::Again => {
+                            recall
+                                .memory
+                                .forget_stability(card.difficulty, card.stability, retrievability)
+                        }
+                        _ => recall.memory.recall_stability(
+                            card.difficulty,
+                            card.stability,
+                            retrievability,
+                            &score,
+                        ),
+                    };
+                    card.difficulty = recall.memory.next_difficulty(card.difficulty, &score);
+                }
+                if card.review.state == ReviewState::Learned && score == ReviewScore::Again {
+                    today.lapses += 1;
+                }
+
+                let review = config.scheduler.schedule(
+                    card.review.clone(),
+                    time,
+                    score,
+                    rng.gen(),
+                    &due_counts,
+                    days,
+                );
+                move_due(&mut due_counts, days, &card.review, &review);
+                card.review = review;
+                card.last_review = time;
+                today.reviews += 1;
+                today.seconds += recall.seconds_per_review;
+            }
+        }
+
+        if !cards.is_empty() {
+            today.retention =
+                cards.iter().map(|card| card.retrievability(day_end)).sum::<f64>() / cards.len() as f64;
+        }
+        simulation.total_reviews += today.reviews;
+        simulation.total_seconds += today.seconds;
+        simulation.retention = today.retention;
+        simulation.days.push(today);
+    }
+
+    simulation
+}
+
+// Simulates from now, with the collection's day boundary
+pub fn run_simulation(context: &Context, config: &SimulationConfig) -> Result<Simulation, String> {
+    if config.days > MAXIMUM_DAYS {
+        return Err(format!("Simulations can cover at most {} days, found {}", MAXIMUM_DAYS, config.days));
+    }
+    if config.deck_size > MAXIMUM_DECK_SIZE {
+        return Err(format!(
+            "Simulated decks can have at most {} cards, found {}",
+            MAXIMUM_DECK_SIZE, config.deck_size
+        ));
+    }
+
+    let start = Utc::now();
+    let days = config::load_day_boundary(&context.get_collection_path(), start)?;
+    Ok(simulate(config, start, days))
+}
+
+// Simulating takes a while, so it runs off the thread that handles commands
+#[tauri::command]
+pub async fn simulate_reviews(state: State<'_, Context>, config: SimulationConfig) -> Result<Simulation, String> {
+    let context = Context::from(state.inner().get_collection_path().as_str());
+    tauri::async_runtime::spawn_blocking(move || run_simulation(&context, &config))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SchedulerConfig;
+    use crate::context::Context;
+    use crate::review::{Algorithm, DayBoundary};
+    use crate::simulator::{run_simulation, simulate, Simulation, SimulationConfig, MAXIMUM_DAYS, MAXIMUM_DECK_SIZE};
+    use chrono::{TimeZone, Utc};
+
+    fn config(seed: u64) -> SimulationConfig {
+        SimulationConfig {
+            deck_size: 100,
+            new_per_day: 10,
+            days: 30,
+            seed,
+            ..SimulationConfig::default()
+        }
+    }
+
+    fn run(config: &SimulationConfig) -> Simulation {
+        let start = Utc.with_ymd_and_hms(2023, 1, 1, 4, 0, 0).unwrap();
+        simulate(config, start, DayBoundary::default())
+    }
+
+    #[test]
+    fn simulation_is_repeatable() {
+        let simulation = run(&config(7));
+
+        assert_eq!(simulation, run(&config(7)));
+        assert_ne!(simulation, run(&config(8)));
+        assert_eq!(30, simulation.days.len());
+        assert_eq!(100, simulation.days.iter().map(|day| day.new).sum::<usize>());
+        assert!(simulation.days.iter().all(|day| day.reviews >= day.new));
+        assert!(simulation.days[10..].iter().all(|day| day.new == 0));
+        assert!(simulation.total_reviews > 100);
+        assert!(simulation.total_seconds > 100.0 * 20.0);
+        assert!(simulation.retention > 0.5 && simulation.retention < 1.0);
+    }
+
+    #[test]
+    fn higher_desired_retention_costs_more_reviews() {
+        let with_retention = |desired_retention: f64| {
+            let mut scheduler = SchedulerConfig {
+                algorithm: Algorithm::Fsrs,
+                ..SchedulerConfig::default()
+            };
+            scheduler.fsrs.desired_retention = desired_retention;
+            run(&SimulationConfig {
+                scheduler,
+                ..config(3)
+            })
+        };
+
+        let relaxed = with_retention(0.8);
+        let strict = with_retention(0.95);
+
+        assert!(strict.total_reviews > relaxed.total_reviews);
+        assert!(strict.retention > relaxed.retention);
+    }
+
+    #[test]
+    fn load_balancing_evens_out_reviews() {
+        // How much the number of reviews varies from day to day, summed over
+        // a few seeds as any one of them can be lucky
+        let variance = |load_balance: bool| {
+            let scheduler = SchedulerConfig {
+                load_balance,
+                ..SchedulerConfig::default()
+            };
+            (0..5)
+                .map(|seed| {
+                    let simulation = run(&SimulationConfig {
+                        scheduler: scheduler.clone(),
+                        days: 60,
+                        ..config(seed)
+                    });
+                    let reviews: Vec<f64> = simulation.days[20..].iter().map(|day| day.reviews as f64).collect();
+                    let mean = reviews.iter().sum::<f64>() / reviews.len() as f64;
+                    reviews.iter().map(|count| (count - mean).powi(2)).sum::<f64>() / reviews.len() as f64
+                })
+                .sum::<f64>()
+        };
+
+        assert!(variance(true) < variance(false));
+    }
+
+    #[test]
+    fn oversized_simulations_are_refused() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let context = Context::from(tempdir.path().to_str().unwrap());
+        let long = SimulationConfig { days: MAXIMUM_DAYS + 1, ..config(0) };
+        let large = SimulationConfig { deck_size: MAXIMUM_DECK_SIZE + 1, ..config(0) };
+
+        assert!(run_simulation(&context, &long).unwrap_err().contains("days"));
+        assert!(run_simulation(&context, &large).unwrap_err().contains("cards"));
+        assert_eq!(30, run_simulation(&context, &config(0)).unwrap().days.len());
+    }
+}
@@ -0,0 +1,571 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use chrono::{Duration, NaiveDate, Utc};
+use tauri::State;
+
+use crate::card;
+use crate::config::{self, DailyGoal, GoalUnit};
+use crate::context::Context;
+use crate::deck;
+use crate::history::{self, EntryKind, ReviewLogEntry};
+use crate::review::{DayBoundary, Review, ReviewScore, ReviewState};
+
+// Intervals are grouped into these ranges of days, the last one open ended
+const INTERVAL_BUCKETS: [(f64, f64); 6] = [
+    (0.0, 1.0),
+    (1.0, 7.0),
+    (7.0, 21.0),
+    (21.0, 90.0),
+    (90.0, 365.0),
+    (365.0, f64::MAX),
+];
+// Ease is grouped in whole hundredths so that rounding errors can't push a
+// value like 2.3 into the bucket below
+const EASE_BUCKET_HUNDREDTHS: i64 = 10;
+// FSRS difficulty runs from 1 to 10 and is grouped by whole points
+const DIFFICULTY_BUCKETS: i64 = 10;
+const HEATMAP_DAYS: i64 = 365;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DailyActivity {
+    pub day: NaiveDate,
+    // Every answer, new cards included
+    pub reviews: usize,
+    pub new: usize,
+    pub answer_millis: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Retention {
+    pub reviews: usize,
+    pub passed: usize,
+    // Share of reviews not answered Again, None without any reviews
+    pub retention: Option<f64>,
+}
+
+impl Retention {
+    fn add(&mut self, passed: bool) {
+        self.reviews += 1;
+        if passed {
+            self.passed += 1;
+        }
+        self.retention = Some(self.passed as f64 / self.reviews as f64);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bucket<T> {
+    pub start: f64,
+    // None for the last, open ended bucket
+    pub end: Option<f64>,
+    pub value: T,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionStats {
+    pub overall: Retention,
+    pub by_deck: BTreeMap<String, Retention>,
+    // By the interval the card was reviewed at
+    pub by_interval: Vec<Bucket<Retention>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateCounts {
+    pub new: usize,
+    pub learned: usize,
+    pub relearning: usize,
+    pub suspended: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CardStats {
+    pub states: StateCounts,
+    // Of cards that have been learned. Cards scheduled by FSRS have no
+    // meaningful ease and are counted by difficulty instead.
+    pub ease: Vec<Bucket<usize>>,
+    pub difficulty: Vec<Bucket<usize>>,
+    pub interval: Vec<Bucket<usize>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeatmapDay {
+    pub day: NaiveDate,
+    pub reviews: usize,
+    pub minutes: f64,
+    // None without a daily goal
+    pub goal_met: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Heatmap {
+    // Every day of the past year, today last
+    pub days: Vec<HeatmapDay>,
+    // Days in a row with at least one review
+    pub current_streak: u32,
+    pub longest_streak: u32,
+    pub goal: Option<DailyGoal>,
+}
+
+// The effective review log entries of every note in the given decks, by deck
+fn read_logs(collection: &str, decks: &[String]) -> BTreeMap<String, Vec<ReviewLogEntry>> {
+    decks
+        .iter()
+        .map(|deck| {
+            let entries = history::read_deck_logs(collection, deck)
+                .into_values()
+                .flat_map(history::effective_entries)
+                .collect();
+            (deck.to_string(), entries)
+        })
+        .collect()
+}
+
+fn interval_buckets<T: Default>() -> Vec<Bucket<T>> {
+    INTERVAL_BUCKETS
+        .iter()
+        .map(|(start, end)| Bucket {
+            start: *start,
+            end: if *end == f64::MAX { None } else { Some(*end) },
+            value: T::default(),
+        })
+        .collect()
+}
+
+fn interval_bucket<T>(buckets: &mut [Bucket<T>], interval: f64) -> Option<&mut Bucket<T>> {
+    buckets
+        .iter_mut()
+        .find(|bucket| bucket.start <= interval && bucket.end.map_or(true, |end| interval < end))
+}
+
+// Buckets `hundredths` wide, from the count in each
+fn counted_buckets(counts: BTreeMap<i64, usize>, hundredths: i64) -> Vec<Bucket<usize>> {
+    counts
+        .into_iter()
+        .map(|(bucket, count)| Bucket {
+            start: (bucket * hundredths) as f64 / 100.0,
+            end: Some(((bucket + 1) * hundredths) as f64 / 100.0),
+            value: count,
+        })
+        .collect()
+}
+
+// Answers and new cards on each day that had any, oldest first. Legacy
+// entries have no timestamps and are left out.
+pub fn get_daily_activity<'a>(
+    entries: impl IntoIterator<Item = &'a ReviewLogEntry>,
+    days: DayBoundary,
+) -> Vec<DailyActivity> {
+    let mut activity: BTreeMap<NaiveDate, DailyActivity> = BTreeMap::new();
+    for entry in entries {
+        if entry.kind != EntryKind::Review || entry.version == 0 {
+            continue;
+        }
+        let day = days.day(entry.reviewed_at);
+        let daily = activity.entry(day).or_insert_with(|| DailyActivity {
+            day,
+            ..DailyActivity::default()
+        });
+        daily.reviews += 1;
+        if entry.is_first_answer() {
+            daily.new += 1;
+        }
+        daily.answer_millis += entry.answer_millis.unwrap_or(0);
+    }
+    activity.into_values().collect()
+}
+
+// True retention only looks at reviews of learned cards, as learning steps
+// come too soon after the previous answer to say much about memory
+pub fn get_retention(logs: &BTreeMap<String, Vec<ReviewLogEntry>>) -> RetentionStats {
+    let mut stats = RetentionStats {
+        by_interval: interval_buckets(),
+        ..RetentionStats::default()
+    };
+    for (deck, entries) in logs {
+        let by_deck = stats.by_deck.entry(deck.to_string()).or_default();
+        for entry in entries {
+            if entry.kind != EntryKind::Review || entry.before.state != ReviewState::Learned {
+                continue;
+            }
+            let passed = entry.score != Some(ReviewScore::Again);
+            stats.overall.add(passed);
+            by_deck.add(passed);
+            if let Some(bucket) = interval_bucket(&mut stats.by_interval, entry.before.interval) {
+                bucket.value.add(passed);
+            }
+        }
+    }
+    stats
+}
+
+pub fn get_card_stats(reviews: &[Review]) -> CardStats {
+    let mut stats = CardStats {
+        interval: interval_buckets(),
+        ..CardStats::default()
+    };
+    let mut ease: BTreeMap<i64, usize> = BTreeMap::new();
+    let mut difficulty: BTreeMap<i64, usize> = BTreeMap::new();
+
+    for review in reviews {
+        if review.suspended {
+            stats.states.suspended += 1;
+        }
+        match review.state {
+            ReviewState::New => stats.states.new += 1,
+            ReviewState::Learned => stats.states.learned += 1,
+            ReviewState::Relearning => stats.states.relearning += 1,
+        }
+        if review.state != ReviewState::New {
+            match review.difficulty {
+                Some(value) => {
+                    let bucket = (value.floor() as i64).clamp(1, DIFFICULTY_BUCKETS - 1);
+                    *difficulty.entry(bucket).or_default() += 1;
+                }
+                None => {
+                    let hundredths = (review.ease * 100.0).round() as i64;
+                    *ease.entry(hundredths / EASE_BUCKET_HUNDREDTHS).or_default() += 1;
+                }
+            }
+            if let Some(bucket) = interval_bucket(&mut stats.interval, review.interval) {
+                bucket.value += 1;
+            }
+        }
+    }
+
+    stats.ease = counted_buckets(ease, EASE_BUCKET_HUNDREDTHS);
+    stats.difficulty = counted_buckets(difficulty, 100);
+    stats
+}
+
+fn goal_met(goal: &DailyGoal, activity: &DailyActivity) -> bool {
+    match goal.unit {
+        GoalUnit::Reviews => activity.reviews >= goal.amount as usize,
+        GoalUnit::Minutes => activity.answer_millis >= u64::from(goal.amount) * 60_000,
+    }
+}
+
+// The current streak still counts when nothing was studied yet today, as
+// long as yesterday was
+fn get_streaks(activity: &[DailyActivity], today: NaiveDate) -> (u32, u32) {
+    let mut run = 0;
+    let mut longest = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for daily in activity.iter().filter(|daily| daily.reviews > 0) {
+        run = match previous {
+            Some(previous) if daily.day - previous == Duration::days(1) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(daily.day);
+    }
+    let current = match previous {
+        Some(last) if today - last <= Duration::days(1) => run,
+        _ => 0,
+    };
+    (current, longest)
+}
+
+// Takes activity sorted by day, as from `get_daily_activity`
+pub fn get_heatmap(activity: &[DailyActivity], today: NaiveDate, goal: Option<DailyGoal>) -> Heatmap {
+    let by_day: BTreeMap<NaiveDate, &DailyActivity> = activity.iter().map(|daily| (daily.day, daily)).collect();
+    let days = (0..HEATMAP_DAYS)
+        .rev()
+        .map(|ago| {
+            let day = today - Duration::days(ago);
+            let daily = by_day.get(&day).map_or_else(
+                || DailyActivity {
+                    day,
+                    ..DailyActivity::default()
+                },
+                |daily| (*daily).clone(),
+            );
+            HeatmapDay {
+                day,
+                reviews: daily.reviews,
+                minutes: daily.answer_millis as f64 / 60_000.0,
+                goal_met: goal.as_ref().map(|goal| goal_met(goal, &daily)),
+            }
+        })
+        .collect();
+    let (current_streak, longest_streak) = get_streaks(activity, today);
+
+    Heatmap {
+        days,
+        current_streak,
+        longest_streak,
+        goal,
+    }
+}
+
+// Activity over the last `days` days for a deck, or the whole collection when
+// no deck is given. Every day with activity when `days` isn't given.
+pub fn daily_activity_stats(
+    context: &Context,
+    deck: Option<&str>,
+    days: Option<u32>,
+) -> Result<Vec<DailyActivity>, String> {
+    let collection = context.get_collection_path();
+    let time = Utc::now();
+    let boundary = config::load_day_boundary(&collection, time)?;
+    let logs = read_logs(&collection, &deck::get_deck_scope(context, deck)?);
+
+    let activity = get_daily_activity(logs.values().flatten(), boundary);
+    Ok(match days {
+        Some(days) => {
+            let first = boundary.day(time) - Duration::days(i64::from(days) - 1);
+            activity.into_iter().filter(|daily| daily.day >= first).collect()
+        }
+        None => activity,
+    })
+}
+
+pub fn retention_stats(context: &Context, deck: Option<&str>) -> Result<RetentionStats, String> {
+    let collection = context.get_collection_path();
+    Ok(get_retention(&read_logs(&collection, &deck::get_deck_scope(context, deck)?)))
+}
+
+// Cards that were never answered count as new
+pub fn card_stats(context: &Context, deck: Option<&str>) -> Result<CardStats, String> {
+    let mut reviews = vec![];
+    for deck in deck::get_deck_scope(context, deck)? {
+        reviews.extend(card::get_deck_cards(context, &deck)?.into_iter().map(Review::from));
+    }
+    Ok(get_card_stats(&reviews))
+}
+
+// The longest streak counts every day in the logs, not just the past year
+pub fn review_heatmap(context: &Context, deck: Option<&str>) -> Result<Heatmap, String> {
+    let collection = context.get_collection_path();
+    let time = Utc::now();
+    let settings = config::load_collection_settings(&collection)?;
+    let boundary = settings.day_boundary(time);
+    let logs = read_logs(&collection, &deck::get_deck_scope(context, deck)?);
+
+    let activity = get_daily_activity(logs.values().flatten(), boundary);
+    Ok(get_heatmap(&activity, boundary.day(time), settings.daily_goal))
+}
+
+#[tauri::command]
+pub fn get_daily_activity_stats(
+    state: State<'_, Context>,
+    deck: Option<String>,
+    days: Option<u32>,
+) -> Result<Vec<DailyActivity>, String> {
+    daily_activity_stats(state.inner(), deck.as_deref(), days)
+}
+
+#[tauri::command]
+pub fn get_retention_stats(state: State<'_, Context>, deck: Option<String>) -> Result<RetentionStats, String> {
+    retention_stats(state.inner(), deck.as_deref())
+}
+
+#[tauri::command]
+pub fn get_card_state_stats(state: State<'_, Context>, deck: Option<String>) -> Result<CardStats, String> {
+    card_stats(state.inner(), deck.as_deref())
+}
+
+#[tauri::command]
+pub fn get_review_heatmap(state: State<'_, Context>, deck: Option<String>) -> Result<Heatmap, String> {
+    review_heatmap(state.inner(), deck.as_deref())
+}
+
+#[tauri::command]
+pub fn set_daily_goal(state: State<'_, Context>, goal: Option<DailyGoal>) -> Result<(), String> {
+    config::save_daily_goal(&state.inner().get_collection_path(), goal.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DailyGoal, GoalUnit, COLLECTION_CONFIG_FILE};
+    use crate::context::Context;
+    use crate::history::ReviewLogEntry;
+    use crate::review::{Review, ReviewScore, ReviewState};
+    use crate::stats::{
+        card_stats, daily_activity_stats, get_card_stats, get_heatmap, retention_stats, DailyActivity, Retention,
+        StateCounts,
+    };
+    use crate::test_helpers::{learned, log};
+    use chrono::{Duration, NaiveDate, Utc};
+    use std::fs;
+    use std::path::Path;
+
+    // Note 1 is learned then lapses at a 10 day interval, note 2 is learned
+    // and recalled at 3 days, note 3 in the other deck is recalled at 100
+    // days. Note 4 is never answered.
+    fn scaffold_collection(collection: &str) {
+        fs::write(
+            Path::new(collection).join(COLLECTION_CONFIG_FILE),
+            "utc_offset_minutes: 0\nrollover_hour: 0\n",
+        )
+        .unwrap();
+        for deck in ["first", "second"] {
+            fs::create_dir_all(Path::new(collection).join(deck)).unwrap();
+        }
+        for (deck, note_id) in [("first", "1"), ("first", "2"), ("second", "3"), ("second", "4")] {
+            fs::write(
+                Path::new(collection).join(deck).join(format!("{}_basic.md", note_id)),
+                "# Front\nQ\n",
+            )
+            .unwrap();
+        }
+
+        let yesterday = Utc::now() - Duration::days(1);
+        let now = Utc::now();
+        let relearning = Review {
+            state: ReviewState::Relearning,
+            ease: 2.3,
+            lapses: 1,
+            ..learned(1)
+        };
+        log(
+            collection,
+            "first",
+            "1",
+            vec![
+                ReviewLogEntry::new(1, yesterday, ReviewScore::Easy, Review::default(), learned(10)),
+                ReviewLogEntry {
+                    answer_millis: Some(4000),
+                    ..ReviewLogEntry::new(1, now, ReviewScore::Again, learned(10), relearning)
+                },
+            ],
+        );
+        log(
+            collection,
+            "first",
+            "2",
+            vec![
+                ReviewLogEntry::new(1, yesterday, ReviewScore::Easy, Review::default(), learned(3)),
+                ReviewLogEntry {
+                    answer_millis: Some(6000),
+                    ..ReviewLogEntry::new(1, now, ReviewScore::Good, learned(3), learned(8))
+                },
+            ],
+        );
+        log(
+            collection,
+            "second",
+            "3",
+            vec![ReviewLogEntry::new(1, now, ReviewScore::Hard, learned(100), learned(120))],
+        );
+    }
+
+    #[test]
+    fn daily_activity_counts_reviews_and_new_cards() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_collection(collection);
+        let context = Context::from(collection);
+
+        let activity = daily_activity_stats(&context, None, None).unwrap();
+
+        assert_eq!(2, activity.len());
+        assert_eq!((2, 2, 0), (activity[0].reviews, activity[0].new, activity[0].answer_millis));
+        assert_eq!((3, 0, 10000), (activity[1].reviews, activity[1].new, activity[1].answer_millis));
+        assert_eq!(1, daily_activity_stats(&context, None, Some(1)).unwrap().len());
+        assert_eq!(2, daily_activity_stats(&context, Some("first"), None).unwrap()[1].reviews);
+    }
+
+    #[test]
+    fn retention_by_deck_and_interval() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_collection(collection);
+        let context = Context::from(collection);
+
+        let retention = retention_stats(&context, None).unwrap();
+
+        assert_eq!(3, retention.overall.reviews);
+        assert_eq!(2, retention.overall.passed);
+        assert_eq!(Some(0.5), retention.by_deck["first"].retention);
+        assert_eq!(Some(1.0), retention.by_deck["second"].retention);
+        let by_interval: Vec<Retention> = retention.by_interval.iter().map(|bucket| bucket.value).collect();
+        assert_eq!(Some(1.0), by_interval[1].retention, "3 days");
+        assert_eq!(Some(0.0), by_interval[2].retention, "10 days");
+        assert_eq!(Some(1.0), by_interval[4].retention, "100 days");
+        assert_eq!(None, by_interval[0].retention);
+    }
+
+    #[test]
+    fn cards_by_state_with_ease_and_interval() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let collection = tempdir.path().to_str().unwrap();
+        scaffold_collection(collection);
+        let context = Context::from(collection);
+
+        let stats = card_stats(&context, None).unwrap();
+
+        assert_eq!(
+            StateCounts {
+                new: 1,
+                learned: 2,
+                relearning: 1,
+                suspended: 0
+            },
+            stats.states
+        );
+        let ease: Vec<(f64, usize)> = stats.ease.iter().map(|bucket| (bucket.start, bucket.value)).collect();
+        assert_eq!(2, ease.len());
+        assert!((ease[0].0 - 2.3).abs() < 1e-9);
+        assert_eq!((1, 2), (ease[0].1, ease[1].1));
+        let intervals: Vec<usize> = stats.interval.iter().map(|bucket| bucket.value).collect();
+        assert_eq!(vec![0, 1, 1, 0, 1, 0], intervals);
+        assert_eq!(None, stats.interval[5].end);
+    }
+
+    #[test]
+    fn ease_and_difficulty_buckets() {
+        let with_ease = |ease: f64| Review { ease, ..learned(1) };
+        let with_difficulty = |difficulty: f64| Review { difficulty: Some(difficulty), ..learned(1) };
+        // FSRS cards are counted by difficulty, 10 going in the last bucket
+        let mut reviews = vec![with_ease(2.3), with_ease(2.36), with_ease(2.4), with_ease(2.5)];
+        reviews.extend(vec![with_difficulty(5.5), with_difficulty(10.0)]);
+
+        let stats = get_card_stats(&reviews);
+
+        let ease: Vec<(f64, Option<f64>, usize)> =
+            stats.ease.iter().map(|bucket| (bucket.start, bucket.end, bucket.value)).collect();
+        assert_eq!(vec![(2.3, Some(2.4), 2), (2.4, Some(2.5), 1), (2.5, Some(2.6), 1)], ease);
+        let difficulty: Vec<(f64, usize)> = stats.difficulty.iter().map(|bucket| (bucket.start, bucket.value)).collect();
+        assert_eq!(vec![(5.0, 1), (9.0, 1)], difficulty);
+    }
+
+    #[test]
+    fn heatmap_streaks_and_goal() {
+        let today = NaiveDate::from_ymd_opt(2023, 6, 15).unwrap();
+        let studied = |ago: i64, reviews: usize, minutes: u64| DailyActivity {
+            day: today - Duration::days(ago),
+            reviews,
+            new: 0,
+            answer_millis: minutes * 60_000,
+        };
+        // A five day streak two years ago, then three days up to yesterday
+        let mut activity: Vec<DailyActivity> = (0..5).rev().map(|ago| studied(800 + ago, 1, 1)).collect();
+        activity.extend([studied(10, 4, 1), studied(3, 30, 5), studied(2, 10, 12), studied(1, 5, 20)]);
+        let goal = DailyGoal {
+            unit: GoalUnit::Minutes,
+            amount: 10,
+        };
+
+        let heatmap = get_heatmap(&activity, today, Some(goal));
+
+        assert_eq!(365, heatmap.days.len());
+        assert_eq!(today, heatmap.days[364].day);
+        assert_eq!(30, heatmap.days[361].reviews);
+        assert_eq!(4, heatmap.days.iter().filter(|day| day.reviews > 0).count());
+        assert_eq!(3, heatmap.current_streak);
+        assert_eq!(5, heatmap.longest_streak);
+        let met: Vec<Option<bool>> = heatmap.days[361..].iter().map(|day| day.goal_met).collect();
+        assert_eq!(vec![Some(false), Some(true), Some(true), Some(false)], met);
+
+        let reviews = DailyGoal {
+            unit: GoalUnit::Reviews,
+            amount: 10,
+        };
+        let heatmap = get_heatmap(&activity, today + Duration::days(2), Some(reviews));
+        assert_eq!(0, heatmap.current_streak);
+        assert_eq!(Some(true), heatmap.days[360].goal_met);
+        assert_eq!(None, get_heatmap(&activity, today, None).days[364].goal_met);
+    }
+}
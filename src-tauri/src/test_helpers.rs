@@ -0,0 +1,35 @@
+// Fixtures shared by the tests of several modules
+
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::history::{append_entry, get_reviews_path, ReviewLogEntry};
+use crate::review::{Review, ReviewScore, ReviewState};
+
+// A learned card with an interval of `days`, due that many days from now
+pub fn learned(days: i64) -> Review {
+    Review::new(Some(Utc::now() + Duration::days(days)), days as f64, 2.5, ReviewState::Learned, 0)
+}
+
+// Appends the entries to the review log of a note
+pub fn log(collection: &str, deck: &str, note_id: &str, entries: Vec<ReviewLogEntry>) {
+    let path = get_reviews_path(collection, deck).join(format!("{}.jsonl", note_id));
+    for entry in entries {
+        append_entry(&path, &entry).unwrap();
+    }
+}
+
+// Logs a Good answer that took the card from `before` to `after`
+pub fn answer(collection: &str, deck: &str, note_id: &str, before: Review, after: Review, time: DateTime<Utc>) {
+    log(collection, deck, note_id, vec![ReviewLogEntry::new(1, time, ReviewScore::Good, before, after)]);
+}
+
+// A deck with two basic notes, 123 and 456, that were never answered
+pub fn scaffold_deck(collection: &str) {
+    let deck_path = Path::new(collection).join("testdeck");
+    fs::create_dir_all(deck_path.join("reviews")).unwrap();
+    fs::write(deck_path.join("123_basic.md"), "# Front\nQ\n# Back\nA\n").unwrap();
+    fs::write(deck_path.join("456_basic.md"), "# Front\nQ\n# Back\nA\n").unwrap();
+}
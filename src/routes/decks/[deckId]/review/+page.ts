@@ -10,14 +10,21 @@ type Card = {
 	state: number
 }
 
+export type SessionStatus = {
+	card: Card | null,
+	counts: {
+		new: number,
+		learning: number,
+		review: number
+	},
+	unreadable_lines: Record<string, number[]>
+}
+
 export const load = async ({ params }: any) => {
-	console.log(params);
 	const { deckId } = params;
-	const cards: Card[] = await tauri.invoke("list_cards_to_review", { deck: deckId });
-
-	console.log(cards);
+	const status: SessionStatus = await tauri.invoke("start_session", { deck: deckId });
 
 	return {
-		cards
+		status
 	};
 };